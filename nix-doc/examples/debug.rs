@@ -42,15 +42,12 @@ fn main() {
         "pprint" => {
             // tests pprint on all the functions it finds
             for node in parsed.node().descendants() {
-                match node.kind() {
-                    NODE_LAMBDA => {
-                        println!("lambda!! {}", node);
-                        let lambda = Lambda::cast(node).unwrap();
-                        println!("pprint_args: {}", pprint::pprint_args(&lambda));
-                        let arg = lambda.arg().unwrap();
-                        println!("args!! {:?} {}", arg.kind(), arg);
-                    }
-                    _ => {}
+                if node.kind() == NODE_LAMBDA {
+                    println!("lambda!! {}", node);
+                    let lambda = Lambda::cast(node).unwrap();
+                    println!("pprint_args: {}", pprint::pprint_args(&lambda));
+                    let arg = lambda.arg().unwrap();
+                    println!("args!! {:?} {}", arg.kind(), arg);
                 }
             }
         }
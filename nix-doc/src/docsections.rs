@@ -0,0 +1,353 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Splitting documentation comments into the sections nixpkgs conventions give them: the older
+//! `Type:` and `Example:` labels as well as RFC 145 style `# Inputs`, `# Type` and `# Examples`
+//! headings
+use serde::Serialize;
+
+/// One documented input of a function, from an `# Inputs` section
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DocInput {
+    /// Name of the input, as written between backticks
+    pub name: String,
+
+    /// Its description
+    pub doc: String,
+}
+
+/// A documentation comment split into its conventional sections
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DocSections {
+    /// The first paragraph, on one line
+    pub summary: String,
+
+    /// The rest of the text that is not part of any of the other sections
+    pub description: String,
+
+    /// The type signature, e.g. `fixedWidthString :: int -> string -> string`
+    #[serde(rename = "type")]
+    pub type_sig: Option<String>,
+
+    /// Documented inputs, in order
+    pub inputs: Vec<DocInput>,
+
+    /// Example code, one entry per example
+    pub examples: Vec<String>,
+}
+
+/// Which section the lines being looked at belong to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Section {
+    Description,
+    /// Under a `# Type` heading
+    Type,
+    /// Under an `# Inputs` heading
+    Inputs,
+    /// Under an `# Examples` heading
+    Examples,
+    /// The indented lines after a `Type:` label
+    LegacyType,
+    /// The indented lines after an `Example:` label
+    LegacyExample,
+}
+
+/// If `line` is a Markdown heading, its level and title
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&ch| ch == '#').count();
+    let title = &line[level..];
+    if level == 0 || !(title.is_empty() || title.starts_with(' ')) {
+        return None;
+    }
+    Some((level, title.trim()))
+}
+
+/// Which section a top level heading with the given title starts, if it is one we know
+fn heading_section(title: &str) -> Option<Section> {
+    match title.to_lowercase().as_str() {
+        "type" => Some(Section::Type),
+        "inputs" | "arguments" => Some(Section::Inputs),
+        "example" | "examples" => Some(Section::Examples),
+        _ => None,
+    }
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+fn is_indented(line: &str) -> bool {
+    line.starts_with(char::is_whitespace)
+}
+
+/// Removes the common indent of `lines` and blank lines around them
+pub(crate) fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or("").trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_matches('\n')
+        .to_string()
+}
+
+/// Pulls the contents of fenced code blocks out of `lines`
+fn code_blocks(lines: &[&str]) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for &line in lines {
+        if is_fence(line) {
+            match current.take() {
+                Some(block) => blocks.push(dedent(&block)),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(block) = &mut current {
+            block.push(line);
+        }
+    }
+    // an unterminated block runs to the end
+    blocks.extend(current.map(|block| dedent(&block)));
+    blocks
+}
+
+/// Parses `` `name` `` lines followed by `: description` definitions
+fn parse_inputs(lines: &[&str]) -> Vec<DocInput> {
+    let mut inputs = Vec::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+    for &line in lines {
+        let trimmed = line.trim();
+        let is_name = trimmed.len() > 2
+            && trimmed.starts_with('`')
+            && trimmed.ends_with('`')
+            && !trimmed[1..trimmed.len() - 1].contains('`');
+        if is_name {
+            inputs.extend(current.take());
+            current = Some((trimmed[1..trimmed.len() - 1].to_string(), Vec::new()));
+        } else if let Some((_, doc)) = &mut current {
+            doc.push(match trimmed.strip_prefix(':') {
+                Some(rest) => rest.trim_start(),
+                None => trimmed,
+            });
+        }
+    }
+    inputs.extend(current);
+    inputs
+        .into_iter()
+        .map(|(name, doc)| DocInput {
+            name,
+            doc: dedent(&doc),
+        })
+        .collect()
+}
+
+impl DocSections {
+    /// Splits up a cleaned up documentation comment
+    pub fn parse(doc: &str) -> Self {
+        let mut section = Section::Description;
+        let mut in_fence = false;
+
+        let mut description = Vec::new();
+        let mut type_lines = Vec::new();
+        let mut input_lines = Vec::new();
+        let mut example_lines = Vec::new();
+        let mut legacy_examples: Vec<Vec<&str>> = Vec::new();
+
+        for line in doc.lines() {
+            if !in_fence {
+                // the legacy sections last as long as the lines are indented
+                let is_legacy = matches!(section, Section::LegacyType | Section::LegacyExample);
+                if is_legacy && !line.trim().is_empty() && !is_indented(line) {
+                    section = Section::Description;
+                }
+
+                if let Some((level, title)) = heading(line) {
+                    match heading_section(title) {
+                        Some(new) if level == 1 => {
+                            section = new;
+                            continue;
+                        }
+                        // some other top level heading ends the section we were in
+                        None if level == 1 => section = Section::Description,
+                        _ => (),
+                    }
+                } else if !is_indented(line) {
+                    if let Some(rest) = line.strip_prefix("Type:") {
+                        let rest = rest.trim();
+                        if rest.is_empty() {
+                            section = Section::LegacyType;
+                        } else {
+                            type_lines.push(rest);
+                        }
+                        continue;
+                    }
+                    if line == "Example:" || line == "Examples:" {
+                        section = Section::LegacyExample;
+                        legacy_examples.push(Vec::new());
+                        continue;
+                    }
+                }
+            }
+            if is_fence(line) {
+                in_fence = !in_fence;
+            }
+
+            match section {
+                Section::Description => description.push(line),
+                Section::Type | Section::LegacyType => type_lines.push(line),
+                Section::Inputs => input_lines.push(line),
+                Section::Examples => example_lines.push(line),
+                Section::LegacyExample => {
+                    if let Some(example) = legacy_examples.last_mut() {
+                        example.push(line);
+                    }
+                }
+            }
+        }
+
+        let type_sig = type_lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !is_fence(line))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut examples = legacy_examples
+            .iter()
+            .map(|example| dedent(example))
+            .collect::<Vec<_>>();
+        let blocks = code_blocks(&example_lines);
+        if blocks.is_empty() {
+            examples.push(dedent(&example_lines));
+        } else {
+            examples.extend(blocks);
+        }
+        examples.retain(|example| !example.is_empty());
+
+        let description = dedent(&description);
+        let (summary, rest) = match description.split_once("\n\n") {
+            Some((summary, rest)) => (summary, rest.trim()),
+            None => (description.as_str(), ""),
+        };
+
+        DocSections {
+            summary: summary.lines().map(str::trim).collect::<Vec<_>>().join(" "),
+            description: rest.to_string(),
+            type_sig: Some(type_sig).filter(|t| !t.is_empty()),
+            inputs: parse_inputs(&input_lines),
+            examples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy() {
+        let doc = r#"Create a fixed width string with additional prefix to match
+required width.
+
+This function will fail if the input string is longer than the
+requested length.
+
+Type: fixedWidthString :: int -> string -> string
+
+Example:
+  fixedWidthString 5 "0" (toString 15)
+  => "00015""#;
+        assert_eq!(
+            DocSections::parse(doc),
+            DocSections {
+                summary: "Create a fixed width string with additional prefix to match required \
+                          width."
+                    .to_string(),
+                description: "This function will fail if the input string is longer than the\n\
+                              requested length."
+                    .to_string(),
+                type_sig: Some("fixedWidthString :: int -> string -> string".to_string()),
+                inputs: Vec::new(),
+                examples: vec!["fixedWidthString 5 \"0\" (toString 15)\n=> \"00015\"".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn rfc145() {
+        let doc = r#"Create a fixed width string.
+
+More words.
+
+# Inputs
+
+`width`
+
+: 1\. Function argument
+
+`filler`
+
+: The string to pad with,
+  usually `"0"`
+
+# Type
+
+```
+fixedWidthString :: int -> string -> string
+```
+
+# Examples
+:::{.example}
+## `lib.strings.fixedWidthString` usage example
+
+```nix
+# a comment, not a heading
+fixedWidthString 5 "0" (toString 15)
+=> "00015"
+```
+
+:::
+
+# Notes
+
+Don't use it for bytes."#;
+        assert_eq!(
+            DocSections::parse(doc),
+            DocSections {
+                summary: "Create a fixed width string.".to_string(),
+                description: "More words.\n\n# Notes\n\nDon't use it for bytes.".to_string(),
+                type_sig: Some("fixedWidthString :: int -> string -> string".to_string()),
+                inputs: vec![
+                    DocInput {
+                        name: "width".to_string(),
+                        doc: r"1\. Function argument".to_string(),
+                    },
+                    DocInput {
+                        name: "filler".to_string(),
+                        doc: "The string to pad with,\nusually `\"0\"`".to_string(),
+                    },
+                ],
+                examples: vec![
+                    "# a comment, not a heading\nfixedWidthString 5 \"0\" (toString 15)\n=> \"00015\""
+                        .to_string()
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn plain() {
+        let sections = DocSections::parse("Adds one to a number");
+        assert_eq!(sections.summary, "Adds one to a number");
+        assert_eq!(sections.description, "");
+        assert_eq!(sections.type_sig, None);
+        assert!(sections.examples.is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Errors of the nix-doc library
+use std::{fmt, io, str};
+
+use crate::types::TypeError;
+use crate::ParseDiagnostic;
+
+macro_rules! impl_from {
+    ($on:ty, $variant:ident, $ty:ty) => {
+        impl From<$ty> for $on {
+            fn from(f: $ty) -> $on {
+                <$on>::$variant(f)
+            }
+        }
+    };
+}
+
+/// Something that went wrong looking at nix files
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written
+    Io(io::Error),
+    /// A file is not valid UTF-8
+    Utf8(str::Utf8Error),
+    /// A syntax error kept a definition from being found
+    Parse(ParseDiagnostic),
+    /// A type query could not be parsed
+    Type(TypeError),
+    /// A 1-based line and column are outside of the file
+    Position { line: usize, column: usize },
+    /// A byte offset is outside of the file or inside of a character
+    Offset { offset: usize },
+    /// No function is defined at a 1-based line and column
+    NoDefinition { line: usize, column: usize },
+}
+
+impl_from!(Error, Io, io::Error);
+impl_from!(Error, Utf8, str::Utf8Error);
+impl_from!(Error, Parse, ParseDiagnostic);
+impl_from!(Error, Type, TypeError);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Utf8(e) => write!(f, "file is not valid UTF-8: {}", e),
+            Error::Parse(diagnostic) => write!(
+                f,
+                "syntax error at line {} column {}: {}",
+                diagnostic.line, diagnostic.column, diagnostic.message
+            ),
+            Error::Type(e) => write!(f, "invalid type: {}", e),
+            Error::Position { line, column } => {
+                write!(f, "line {} column {} is outside of the file", line, column)
+            }
+            Error::Offset { offset } => write!(
+                f,
+                "byte offset {} is outside of the file or inside of a character",
+                offset
+            ),
+            Error::NoDefinition { line, column } => {
+                write!(
+                    f,
+                    "no function is defined at line {} column {}",
+                    line, column
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Utf8(e) => Some(e),
+            Error::Type(e) => Some(e),
+            _ => None,
+        }
+    }
+}
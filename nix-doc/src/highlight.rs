@@ -0,0 +1,56 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Syntax highlighting of nix source for the terminal
+use colorful::{Color, Colorful};
+use rnix::tokenizer::Tokenizer;
+use rnix::SyntaxKind::{self, *};
+
+use crate::FormatOptions;
+
+fn paint(kind: SyntaxKind, text: &str, options: &FormatOptions) -> String {
+    match kind {
+        TOKEN_COMMENT => options.paint(text, |s| s.color(Color::Grey50)),
+        TOKEN_STRING_START | TOKEN_STRING_CONTENT | TOKEN_STRING_END | TOKEN_PATH | TOKEN_URI => {
+            options.paint(text, |s| s.green())
+        }
+        TOKEN_INTEGER | TOKEN_FLOAT => options.paint(text, |s| s.magenta()),
+        TOKEN_ASSERT | TOKEN_ELSE | TOKEN_IF | TOKEN_IN | TOKEN_INHERIT | TOKEN_LET | TOKEN_REC
+        | TOKEN_THEN | TOKEN_WITH => options.paint(text, |s| s.blue().bold()),
+        TOKEN_INTERPOL_START | TOKEN_INTERPOL_END | TOKEN_DYNAMIC_START | TOKEN_DYNAMIC_END => {
+            options.paint(text, |s| s.cyan())
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// Highlights nix `source`, which doesn't have to be a complete expression, if colours are enabled
+pub fn highlight(source: &str, options: &FormatOptions) -> String {
+    if !options.color {
+        return source.to_string();
+    }
+    Tokenizer::new(source)
+        .map(|(kind, text)| paint(kind, &text, options))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn lossless() {
+        let source = "f = { a ? 1 }: let b = \"${a}\"; in # hi\n  if a then ./x else b;";
+        let options = FormatOptions {
+            color: true,
+            ..FormatOptions::plain()
+        };
+        let highlighted = highlight(source, &options);
+        assert!(highlighted.contains(&"let".blue().bold().to_string()));
+        assert!(highlighted.contains(&"# hi".color(Color::Grey50).to_string()));
+        let escapes = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        assert_eq!(escapes.replace_all(&highlighted, ""), source);
+    }
+}
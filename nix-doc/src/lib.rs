@@ -3,107 +3,769 @@
 // SPDX-License-Identifier: BSD-2-Clause OR MIT
 
 //! library components of nix-doc
+pub mod docsections;
+pub mod error;
+pub mod highlight;
+pub mod lineindex;
+pub mod markdown;
+pub mod nixpath;
 pub mod pprint;
 pub mod tags;
 pub mod threadpool;
+pub mod types;
+pub mod walk;
 
-use crate::pprint::pprint_args;
+use crate::docsections::DocSections;
+use crate::lineindex::{ColumnUnit, LineIndex};
+use crate::pprint::{FormalDoc, PatternInfo, Signature};
 use crate::threadpool::ThreadPool;
+use crate::types::{Type, TypeMatch};
+use crate::walk::WalkOptions;
 
 use colorful::{Color, Colorful};
-use regex::Regex;
-use rnix::types::{AttrSet, EntryHolder, Ident, Lambda, TokenWrapper, TypedNode};
+use ignore::DirEntry;
+use regex::{Regex, RegexBuilder};
+use rnix::parser::ParseError;
+use rnix::types::{
+    Assert, AttrSet, EntryHolder, Ident, KeyValue, Lambda, LegacyLet, LetIn, TokenWrapper,
+    TypedNode, With, Wrapper,
+};
 use rnix::SyntaxKind::*;
 use rnix::{NodeOrToken, SyntaxNode, TextUnit, WalkEvent, AST};
-use walkdir::{DirEntry, WalkDir};
+use serde::Serialize;
 
-use std::fs;
-use std::iter;
-use std::path::Path;
-use std::sync::mpsc::channel;
-use std::{fmt::Display, str};
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::{env, fs};
+use std::{str, vec};
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub use crate::error::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 const DOC_INDENT: usize = 3;
 
-/// Max size of files we will consider searching. It takes a long time to parse 300k lines of nix
-/// in hackage-packages.nix and no files this big will have search results in them as they
+/// Default max size of files we will consider searching. It takes a long time to parse 300k lines
+/// of nix in hackage-packages.nix and no files this big will have search results in them as they
 /// categorically do not contain functions. 200k bytes is ~7.5k lines
-const MAX_FILE_SIZE: u64 = 200_000;
+pub const MAX_FILE_SIZE: u64 = 200_000;
+
+/// How a function found by a search is bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BindingKind {
+    /// An attribute of an attribute set (including `rec` sets), visible from outside
+    Attribute,
+    /// A binding in a `let ... in` or legacy `let { ... }` block, only visible in its scope
+    Local,
+    /// The whole file, like the package functions given to `callPackage`
+    File,
+}
+
+/// What kind of text a documentation comment holds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocFormat {
+    /// Ordinary `#` or `/* */` comments, shown as written
+    #[default]
+    Plain,
+    /// An RFC 145 `/** */` doc comment, which holds Markdown
+    Markdown,
+}
+
+/// How well a search result matched the query, best first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchQuality {
+    /// The query matched the whole name or attribute path
+    Exact,
+    /// The query matched the start of the name or attribute path
+    Prefix,
+    /// The query matched somewhere in the name or attribute path
+    Substring,
+    /// The query only matched the documentation
+    Doc,
+}
 
-struct SearchResult {
+/// How to order search results
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Best matches first, then shallower files first
+    Relevance,
+    /// By file path, then position in the file
+    Path,
+}
+
+impl str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "relevance" => Ok(SortOrder::Relevance),
+            "path" => Ok(SortOrder::Path),
+            _ => Err(format!(
+                "unknown sort order {:?}, expected one of: relevance, path",
+                s
+            )),
+        }
+    }
+}
+
+/// A documented function found by [`search`] or [`search_file`]
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchResult {
     /// Name of the function
-    identifier: String,
+    pub identifier: String,
+
+    /// Full dotted attribute path of the function, including the keys of enclosing attrsets
+    pub path: String,
 
     /// Dedented documentation comments
-    doc: String,
+    pub doc: String,
+
+    /// Whether `doc` is Markdown
+    pub doc_format: DocFormat,
+
+    /// Byte ranges of `doc` matched by the search, sorted and non-overlapping
+    pub doc_matches: Vec<Range<usize>>,
+
+    /// `doc` split into summary, type, inputs and examples
+    pub sections: DocSections,
 
     /// Parameter block for the function
-    param_block: String,
+    pub param_block: String,
+
+    /// The arguments of the function, for rendering `param_block` to fit the terminal
+    #[serde(skip)]
+    pub signature: Signature,
+
+    /// Formals of the `{ ... }` pattern argument of the function, if it has one
+    pub pattern: Option<PatternInfo>,
+
+    /// Documentation of the formals, from comments next to them in the pattern
+    pub arguments: Vec<FormalDoc>,
+
+    /// Dedented source of the definition, from the binding to the end of the function, if it was
+    /// asked for with [`SearchOptions::source`]
+    pub source: Option<String>,
+
+    /// Whether the function is an attribute or a local `let` binding
+    pub binding: BindingKind,
+
+    /// How well the function matched the query
+    pub quality: MatchQuality,
+
+    /// How well the declared type of the function matched the type query, if there was one
+    pub type_match: Option<TypeMatch>,
 
-    /// Start of the definition of the function
-    defined_at_start: usize,
+    /// Name of the [`SearchRoot`] the function was found under
+    pub root: String,
+
+    /// File the function was found in
+    pub file: PathBuf,
+
+    /// Number of directories between the search root and the file
+    #[serde(skip)]
+    pub depth: usize,
+
+    /// 1-based line the function is defined on
+    pub line: usize,
+
+    /// 1-based column the function is defined at, in characters
+    pub column: usize,
+
+    /// Start of the definition of the function, as a byte offset into the file
+    #[serde(skip)]
+    pub defined_at_start: usize,
+
+    /// Byte range of the definition in the file, from the start of the binding to the end of the
+    /// function
+    #[serde(skip)]
+    pub definition: Range<usize>,
+}
+
+/// When to use colours in output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Use colours if stdout is a terminal and `NO_COLOR` is not set
+    Auto,
+    /// Always use colours
+    Always,
+    /// Never use colours
+    Never,
+}
+
+impl ColorChoice {
+    /// Should colours actually be used?
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                // https://no-color.org: any non-empty value disables colour
+                let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                !no_color && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+impl str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown colour choice {:?}, expected one of: auto, always, never",
+                s
+            )),
+        }
+    }
 }
 
-fn find_line(file: &str, pos: usize) -> usize {
-    file[..pos].lines().count()
+/// Width of the terminal stdout goes to, if it is one
+pub fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize)
+}
+
+/// How to render results for display
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FormatOptions {
+    /// Use ANSI escapes for colours and highlighting
+    pub color: bool,
+
+    /// Show which search root each result came from
+    pub show_root: bool,
+
+    /// Only show the signature and the summary line of the docs
+    pub summary: bool,
+
+    /// Render Markdown docs for a terminal instead of showing them as written
+    pub render_markdown: bool,
+
+    /// Columns to wrap rendered Markdown to
+    pub width: Option<usize>,
+
+    /// Only show this many lines of the source of each function
+    pub source_lines: Option<usize>,
 }
 
-fn find_pos(file: &str, line: usize, col: usize) -> usize {
-    let mut lines = 1;
-    let mut line_start = 0;
-    for (count, ch) in file.chars().enumerate() {
-        if ch == '\n' {
-            lines += 1;
-            line_start = count;
+impl FormatOptions {
+    /// Plain text with docs as written, for consumers that are not terminals
+    pub fn plain() -> Self {
+        FormatOptions::default()
+    }
+
+    /// Output for a terminal, with colours for the given choice and Markdown rendered to fit the
+    /// terminal if stdout is one
+    pub fn with_color(choice: ColorChoice) -> Self {
+        FormatOptions {
+            color: choice.enabled(),
+            render_markdown: true,
+            width: terminal_width(),
+            ..FormatOptions::default()
         }
-        if lines == line && count - line_start == col {
-            return count;
+    }
+
+    /// Applies `style` to `s` if colours are enabled
+    pub fn paint<F, D>(&self, s: &str, style: F) -> String
+    where
+        F: FnOnce(&str) -> D,
+        D: Display,
+    {
+        if self.color {
+            style(s).to_string()
+        } else {
+            s.to_string()
         }
     }
-    unreachable!();
 }
 
 impl SearchResult {
-    fn format<P: Display>(&self, filename: P, line: usize) -> String {
+    /// Formats the result for display
+    pub fn format(&self, options: &FormatOptions) -> String {
+        if options.summary {
+            return self.format_summary(options);
+        }
+        let binding = self.binding_prefix();
+        let doc = if self.doc.is_empty() {
+            options.paint(&indented("(no documentation)", DOC_INDENT), |s| {
+                s.color(Color::Grey50)
+            })
+        } else if options.render_markdown && self.doc_format == DocFormat::Markdown {
+            let width = options.width.map(|w| w.saturating_sub(DOC_INDENT));
+            indented(
+                &markdown::render(&self.doc, &self.doc_matches, width, options),
+                DOC_INDENT,
+            )
+        } else {
+            indented(
+                &highlighted(&self.doc, &self.doc_matches, options),
+                DOC_INDENT,
+            )
+        };
+        let doc = if self.arguments.is_empty() {
+            doc
+        } else {
+            format!(
+                "{}\n\n{}",
+                doc,
+                indented(&self.format_arguments(options), DOC_INDENT)
+            )
+        };
+        // the signature has whatever is left of the line after the name
+        let width = options
+            .width
+            .map(|w| w.saturating_sub(binding.len() + self.path.chars().count() + " = ".len()));
+        let mut out = format!(
+            "{}\n{}{} = {}\n# {}",
+            doc,
+            binding,
+            options.paint(&self.path, |s| s.white().bold()),
+            self.signature.render(width),
+            self.location(options),
+        );
+        if let Some(source) = &self.source {
+            out.push_str("\n\n");
+            out.push_str(&indented(&self.format_source(source, options), DOC_INDENT));
+        }
+        out
+    }
+
+    /// Highlights the source of the function, cut down to `options.source_lines`
+    fn format_source(&self, source: &str, options: &FormatOptions) -> String {
+        let total = source.lines().count();
+        let shown = options.source_lines.unwrap_or(total).min(total);
+        let kept = source.lines().take(shown).collect::<Vec<_>>().join("\n");
+        let mut out = highlight::highlight(&kept, options);
+        if shown < total {
+            out.push('\n');
+            out.push_str(
+                &options.paint(&format!("… {} more lines", total - shown), |s| {
+                    s.color(Color::Grey50)
+                }),
+            );
+        }
+        out
+    }
+
+    /// Does the function have a doc comment or comments on its formals?
+    pub fn is_documented(&self) -> bool {
+        !self.doc.is_empty() || !self.arguments.is_empty()
+    }
+
+    /// Formats the documented formals as a table
+    fn format_arguments(&self, options: &FormatOptions) -> String {
+        let name_width = self
+            .arguments
+            .iter()
+            .map(|arg| arg.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut out = options.paint("Arguments:", |s| s.bold());
+        for arg in &self.arguments {
+            let padding = " ".repeat(name_width - arg.name.chars().count() + 2);
+            // continuation lines of the doc line up with its first line
+            let doc = arg
+                .doc
+                .replace('\n', &format!("\n{}", " ".repeat(name_width + 4)));
+            out.push_str(&format!(
+                "\n  {}{}{}",
+                options.paint(&arg.name, |s| s.white().bold()),
+                padding,
+                doc
+            ));
+        }
+        out
+    }
+
+    /// Where the function is, as `file:line`, with the root it was found in if there are several
+    fn location(&self, options: &FormatOptions) -> String {
+        let mut location = format!("{}:{}", self.file.display(), self.line);
+        if options.show_root && !self.root.is_empty() {
+            location.push_str(&format!(" (in {})", self.root));
+        }
+        location
+    }
+
+    /// Formats the result as its type or signature with the summary of its docs and its location
+    /// below
+    fn format_summary(&self, options: &FormatOptions) -> String {
+        let signature = match &self.sections.type_sig {
+            // the type usually repeats the name
+            Some(ty) => match ty.split_once("::") {
+                Some((_, ty)) => format!(":: {}", ty.trim()),
+                None => format!(":: {}", ty),
+            },
+            None => format!("= {}", self.param_block),
+        };
+        let summary = if self.sections.summary.is_empty() {
+            options.paint("(no documentation)", |s| s.color(Color::Grey50))
+        } else {
+            self.sections.summary.clone()
+        };
         format!(
-            "{}\n{} = {}\n# {}",
-            indented(&self.doc, DOC_INDENT),
-            self.identifier.as_str().white().bold(),
-            self.param_block,
-            format!("{}:{}", filename, line).as_str(),
+            "{}{} {}\n{}  # {}",
+            self.binding_prefix(),
+            options.paint(&self.path, |s| s.white().bold()),
+            signature,
+            indented(&summary, DOC_INDENT),
+            self.location(options),
         )
     }
+
+    fn binding_prefix(&self) -> &'static str {
+        match self.binding {
+            BindingKind::Attribute | BindingKind::File => "",
+            BindingKind::Local => "let ",
+        }
+    }
+}
+
+/// Which parts of a function the search query is matched against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchTarget {
+    /// The function name and its full attribute path
+    Name,
+    /// The cleaned up documentation comment
+    Doc,
+    /// Either of the above
+    Both,
+}
+
+impl str::FromStr for SearchTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SearchTarget::Name),
+            "doc" => Ok(SearchTarget::Doc),
+            "both" => Ok(SearchTarget::Both),
+            _ => Err(format!(
+                "unknown search target {:?}, expected one of: name, doc, both",
+                s
+            )),
+        }
+    }
+}
+
+/// What a search is looking for
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// Regexes that all have to match somewhere in the `target` of a function
+    pub matching: Vec<Regex>,
+
+    /// What the regexes are matched against
+    pub target: SearchTarget,
+
+    /// Also return functions that have no documentation comment
+    pub include_undocumented: bool,
+
+    /// Only return functions with a declared type that matches this one
+    pub type_query: Option<Type>,
+
+    /// Only return functions with a pattern argument that has all of these formals. Files that
+    /// are a function, like packages, are also looked at when this is set.
+    pub formals: Vec<String>,
+
+    /// Fill in [`SearchResult::source`] with the definition of each function
+    pub source: bool,
+}
+
+impl Default for SearchOptions {
+    /// Matches every documented function by name
+    fn default() -> Self {
+        SearchOptions {
+            matching: Vec::new(),
+            target: SearchTarget::Name,
+            include_undocumented: false,
+            type_query: None,
+            formals: Vec::new(),
+            source: false,
+        }
+    }
 }
 
-/// Should the given path be searched?
-/// TODO: support globbing for files e.g. with lib in their name to improve perf significantly
-///       or avoid looking in absurdly large files like hackage.nix
+impl SearchOptions {
+    /// Searches for documented functions with names matching `matching`
+    pub fn new(matching: Regex) -> Self {
+        SearchOptions {
+            matching: vec![matching],
+            ..Self::default()
+        }
+    }
+
+    /// Searches for functions taking all of the `formals`, whatever their name. Such functions
+    /// are rarely documented, so undocumented ones are included too.
+    pub fn by_formals(formals: Vec<String>) -> Self {
+        SearchOptions {
+            include_undocumented: true,
+            formals,
+            ..Self::default()
+        }
+    }
+
+    /// Searches for functions with a declared type matching `query`, whatever their name
+    pub fn by_type(query: Type) -> Self {
+        SearchOptions {
+            type_query: Some(query),
+            ..Self::default()
+        }
+    }
+
+    /// Searches for documented functions containing all the whitespace separated words in
+    /// `query`, ignoring case
+    pub fn keywords(query: &str) -> Self {
+        let matching = query
+            .split_whitespace()
+            .map(|word| {
+                RegexBuilder::new(&regex::escape(word))
+                    .case_insensitive(true)
+                    .build()
+                    .expect("escaped regexes are always valid")
+            })
+            .collect();
+        SearchOptions {
+            matching,
+            ..Self::default()
+        }
+    }
+
+    /// Does `res` take all the formals we are looking for?
+    fn takes(&self, res: &SearchResult) -> bool {
+        match &res.pattern {
+            Some(pattern) => pattern.takes_all(&self.formals),
+            None => self.formals.is_empty(),
+        }
+    }
+
+    /// How well a function with these `names` and `doc` matches the query, if it does. Every
+    /// regex has to match either a name or the docs, and the result is only as good as its worst
+    /// match.
+    fn quality(&self, names: &[&str], doc: &str) -> Option<MatchQuality> {
+        let name_quality = |re: &Regex| match self.target {
+            SearchTarget::Doc => None,
+            SearchTarget::Name | SearchTarget::Both => match_quality(re, names),
+        };
+        self.matching
+            .iter()
+            .map(|re| {
+                name_quality(re).or_else(|| {
+                    if self.target != SearchTarget::Name && re.is_match(doc) {
+                        Some(MatchQuality::Doc)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|quality| quality.into_iter().max().unwrap_or(MatchQuality::Exact))
+    }
+
+    /// Finds the parts of `doc` matched by the query, if the query looks at docs
+    fn doc_matches(&self, doc: &str) -> Vec<Range<usize>> {
+        if self.target == SearchTarget::Name {
+            return Vec::new();
+        }
+        let mut matches = self
+            .matching
+            .iter()
+            .flat_map(|re| re.find_iter(doc).map(|m| m.range()))
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|range| range.start);
+
+        // merge overlapping matches from different keywords
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in matches {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+/// Should the given path be searched? Further filtering is done by [`WalkOptions`].
 pub fn is_searchable(fname: &Path) -> bool {
     fname.to_str().map(|s| s.ends_with(".nix")).unwrap_or(false)
 }
 
-/// Runs a search for files matching the regex `matching`. Returns a list of such results with the
-/// associated file contents
-fn search_file(file: &Path, matching: &Regex) -> Result<Vec<(SearchResult, usize)>> {
-    // don't bother searching files that are so large they must be generated
-    let length = fs::metadata(file)?.len();
-    if length > MAX_FILE_SIZE {
-        return Ok(Vec::new());
-    }
+/// A syntax error found while searching a file. The rest of the file is still searched.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ParseDiagnostic {
+    /// File the error is in
+    pub file: PathBuf,
+
+    /// 1-based line of the error
+    pub line: usize,
 
-    let content = fs::read_to_string(file)?;
-    let ast = rnix::parse(&content).as_result()?;
-    let results = search_ast(&matching, &ast);
+    /// 1-based column of the error, in characters
+    pub column: usize,
 
-    Ok(results
-        .into_iter()
-        .map(|res| {
-            let line = find_line(&content, res.defined_at_start);
-            (res, line)
+    /// What went wrong
+    pub message: String,
+}
+
+impl ParseDiagnostic {
+    fn new(file: &Path, index: &LineIndex, err: &ParseError) -> Result<Self> {
+        let wanted = |kinds: &[rnix::SyntaxKind]| {
+            kinds
+                .iter()
+                .map(|k| format!("{:?}", k))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let (pos, message) = match err {
+            ParseError::Unexpected(range) => (range.start(), "unexpected syntax".to_string()),
+            ParseError::UnexpectedExtra(range) => (
+                range.start(),
+                "unexpected token after the end of the expression".to_string(),
+            ),
+            ParseError::UnexpectedWanted(got, range, kinds) => (
+                range.start(),
+                format!("unexpected {:?}, wanted any of {}", got, wanted(kinds)),
+            ),
+            ParseError::UnexpectedDoubleBind(range) => {
+                (range.start(), "pattern is bound twice".to_string())
+            }
+            ParseError::UnexpectedEOF => (
+                TextUnit::of_str(index.text()),
+                "unexpected end of file".to_string(),
+            ),
+            ParseError::UnexpectedEOFWanted(kinds) => (
+                TextUnit::of_str(index.text()),
+                format!("unexpected end of file, wanted any of {}", wanted(kinds)),
+            ),
+        };
+        let (line, column) = index.position(pos.to_usize(), ColumnUnit::Char)?;
+        Ok(ParseDiagnostic {
+            file: file.to_owned(),
+            line,
+            column,
+            message,
         })
-        .collect::<Vec<_>>())
+    }
+}
+
+impl Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.message
+        )
+    }
+}
+
+/// A file that could not be searched at all, e.g. because it couldn't be read
+#[derive(Debug)]
+pub struct FileError {
+    /// The file
+    pub file: PathBuf,
+
+    /// What went wrong
+    pub error: Error,
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.error)
+    }
+}
+
+/// Runs a search for functions matching `options` in the given file.
+pub fn search_file(file: &Path, options: &SearchOptions) -> Result<Vec<SearchResult>> {
+    search_file_with_errors(file, options).map(|(results, _)| results)
+}
+
+/// Runs a search for functions matching `options` in the given file, also returning its syntax
+/// errors. Functions in the well-formed parts of a file with errors are still found.
+pub fn search_file_with_errors(
+    file: &Path,
+    options: &SearchOptions,
+) -> Result<(Vec<SearchResult>, Vec<ParseDiagnostic>)> {
+    let content = fs::read(file)?;
+    search_source(file, str::from_utf8(&content)?, options)
+}
+
+/// Runs a search for functions matching `options` in `content`, which is named `file`
+fn search_source(
+    file: &Path,
+    content: &str,
+    options: &SearchOptions,
+) -> Result<(Vec<SearchResult>, Vec<ParseDiagnostic>)> {
+    let index = LineIndex::new(content);
+    let ast = rnix::parse(content);
+    let errors = ast
+        .errors()
+        .iter()
+        .map(|err| ParseDiagnostic::new(file, &index, err))
+        .collect::<Result<_>>()?;
+    let mut results = search_ast(options, &ast);
+    // functions that are the whole file only get their name here, so the query is matched against
+    // it here too
+    results.retain_mut(|res| {
+        if res.binding != BindingKind::File {
+            return true;
+        }
+        res.identifier = file_function_name(file);
+        res.path = res.identifier.clone();
+        res.doc_matches = options.doc_matches(&res.doc);
+        match options.quality(&[&res.identifier], &res.doc) {
+            Some(quality) => {
+                res.quality = quality;
+                true
+            }
+            None => false,
+        }
+    });
+
+    for res in results.iter_mut() {
+        res.file = file.to_owned();
+        let (line, column) = index.position(res.defined_at_start, ColumnUnit::Char)?;
+        res.line = line;
+        res.column = column;
+        if options.source {
+            res.source = Some(definition_source(content, res.definition.clone()));
+        }
+    }
+    Ok((results, errors))
+}
+
+/// Dedented text of `definition` in `file`, as if it started at the beginning of its line
+fn definition_source(file: &str, definition: Range<usize>) -> String {
+    let line_start = file[..definition.start].rfind('\n').map_or(0, |i| i + 1);
+    let before = &file[line_start..definition.start];
+    let padded = if before.trim().is_empty() {
+        file[line_start..definition.end].to_string()
+    } else {
+        // keep the rest of the definition lined up with its first line
+        " ".repeat(before.chars().count()) + &file[definition]
+    };
+    docsections::dedent(&padded.lines().collect::<Vec<_>>())
+}
+
+/// Name for a function that is a whole file: the file name, or the directory for `default.nix`
+fn file_function_name(file: &Path) -> String {
+    let name = match file.file_stem() {
+        Some(stem) if stem == "default" => file.parent().and_then(Path::file_name),
+        stem => stem,
+    };
+    name.map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
 }
 
 /// Is a file hidden or a unicode decode error?
@@ -116,74 +778,181 @@ pub fn is_ignored(entry: &DirEntry) -> bool {
         .unwrap_or(true)
 }
 
-/// Search the `dir` for files with function definitions matching `matching`
-pub fn search<F>(dir: &Path, matching: Regex, should_search: F)
-where
-    F: Fn(&Path) -> bool,
-{
+/// What searching one file sends back to the [`SearchResults`]
+type FileOutcome = std::result::Result<(Vec<SearchResult>, Vec<ParseDiagnostic>), FileError>;
+
+/// Iterator over the results of a [`search`], in the order the files finished being searched.
+///
+/// Dropping it before it is exhausted makes the workers skip the files they have not started on
+/// yet, and waits for them to finish the ones they are in the middle of.
+pub struct SearchResults {
+    rx: Receiver<FileOutcome>,
+    current: vec::IntoIter<SearchResult>,
+    parse_errors: Vec<ParseDiagnostic>,
+    errors: Vec<FileError>,
+    /// Set when the iterator is dropped, telling the workers that nobody wants more results
+    cancelled: Arc<AtomicBool>,
+    _pool: ThreadPool,
+}
+
+impl Drop for SearchResults {
+    fn drop(&mut self) {
+        // runs before the pool is dropped and joins the workers
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl SearchResults {
+    /// Syntax errors in the files searched so far. All of them are known once the iterator is
+    /// exhausted.
+    pub fn parse_errors(&self) -> &[ParseDiagnostic] {
+        &self.parse_errors
+    }
+
+    /// Files searched so far that couldn't be read. All of them are known once the iterator is
+    /// exhausted.
+    pub fn errors(&self) -> &[FileError] {
+        &self.errors
+    }
+}
+
+impl Iterator for SearchResults {
+    type Item = SearchResult;
+
+    fn next(&mut self) -> Option<SearchResult> {
+        loop {
+            if let Some(result) = self.current.next() {
+                return Some(result);
+            }
+            match self.rx.recv().ok()? {
+                Ok((results, parse_errors)) => {
+                    self.current = results.into_iter();
+                    self.parse_errors.extend(parse_errors);
+                }
+                Err(err) => self.errors.push(err),
+            }
+        }
+    }
+}
+
+/// A directory to search
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchRoot {
+    /// Name to tag results from this directory with, e.g. `<nixpkgs>`
+    pub name: String,
+
+    /// The directory itself
+    pub path: PathBuf,
+}
+
+impl SearchRoot {
+    /// A root named after its path
+    pub fn new(path: PathBuf) -> Self {
+        SearchRoot {
+            name: path.display().to_string(),
+            path,
+        }
+    }
+}
+
+/// Search the files in each of the `roots` selected by `walk` for function definitions matching
+/// `options`. The files are listed before this returns and searched in the background; see
+/// [`SearchResults`] for stopping early.
+pub fn search(roots: &[SearchRoot], options: &SearchOptions, walk: &WalkOptions) -> SearchResults {
     let pool = ThreadPool::default();
     let (tx, rx) = channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
 
-    //println!("searching {}", dir.display());
-    for direntry in WalkDir::new(dir)
-        .into_iter()
-        .filter_entry(|e| !is_ignored(e))
-        .filter_map(|e| e.ok())
-        .filter(|e| should_search(e.path()) && e.path().is_file())
-    {
-        let my_tx = tx.clone();
-        let matching = matching.clone();
-        pool.push(move || {
-            //println!("{}", direntry.path().display());
-            let results = search_file(direntry.path(), &matching);
-            if let Err(err) = results {
-                eprintln!("Failure handling {}: {}", direntry.path().display(), err);
-                return;
-            }
-            let results = results.unwrap();
+    for root in roots {
+        //println!("searching {}", root.path.display());
+        for path in walk.files(&root.path) {
+            let my_tx = tx.clone();
+            let options = options.clone();
+            let root = root.clone();
+            let cancelled = cancelled.clone();
+            pool.push(move || {
+                //println!("{}", path.display());
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let (mut results, parse_errors) = match search_file_with_errors(&path, &options) {
+                    Ok(found) => found,
+                    Err(error) => {
+                        let _ = my_tx.send(Err(FileError { file: path, error }));
+                        return;
+                    }
+                };
 
-            let formatted = results
-                .iter()
-                .map(|(result, line)| result.format(direntry.path().display(), *line))
-                .collect::<Vec<_>>();
-            if !formatted.is_empty() {
-                my_tx
-                    .send(formatted)
-                    .expect("failed to send messages to display");
-            }
-        });
+                let depth = path
+                    .strip_prefix(&root.path)
+                    .map_or(0, |p| p.components().count().saturating_sub(1));
+                for result in results.iter_mut() {
+                    result.root = root.name.clone();
+                    result.depth = depth;
+                }
+
+                if !results.is_empty() || !parse_errors.is_empty() {
+                    // if this fails, the results iterator was dropped and nobody wants these
+                    let _ = my_tx.send(Ok((results, parse_errors)));
+                }
+            });
+        }
     }
 
     drop(tx);
     pool.done();
 
-    let line = iter::repeat("─")
-        .take(45)
-        .collect::<String>()
-        .color(Color::Grey27);
-    let mut is_first = true;
+    SearchResults {
+        rx,
+        current: Vec::new().into_iter(),
+        parse_errors: Vec::new(),
+        errors: Vec::new(),
+        cancelled,
+        _pool: pool,
+    }
+}
 
-    while let Ok(results) = rx.recv() {
-        for result in results {
-            if !is_first {
-                println!("{}", &line);
-            } else {
-                is_first = false;
-            }
-            println!("{}", result);
-        }
+/// Sorts search results into a deterministic order, independent of the order the files were
+/// searched in
+pub fn sort_results(results: &mut [SearchResult], order: SortOrder) {
+    let by_location = |a: &SearchResult, b: &SearchResult| {
+        a.file
+            .cmp(&b.file)
+            .then_with(|| a.defined_at_start.cmp(&b.defined_at_start))
+    };
+    match order {
+        SortOrder::Relevance => results.sort_by(|a, b| {
+            a.type_match
+                .cmp(&b.type_match)
+                .then_with(|| a.quality.cmp(&b.quality))
+                // favour things like lib/ over pkgs/development/.../
+                .then_with(|| a.depth.cmp(&b.depth))
+                .then_with(|| by_location(a, b))
+        }),
+        SortOrder::Path => results.sort_by(by_location),
     }
 }
 
-/// Searches the given AST for functions called `identifier`
-fn search_ast(identifier: &Regex, ast: &AST) -> Vec<SearchResult> {
+/// Searches the given AST for functions matching `options`
+fn search_ast(options: &SearchOptions, ast: &AST) -> Vec<SearchResult> {
     let mut results = Vec::new();
+    if !options.formals.is_empty() {
+        results.extend(visit_file_function(options, ast));
+    }
     for ev in ast.node().preorder_with_tokens() {
         match ev {
             WalkEvent::Enter(enter) => {
                 //println!("enter {:?}", &enter);
-                if let Some(set) = enter.into_node().and_then(AttrSet::cast) {
-                    results.extend(visit_attrset(identifier, &set));
+                let node = match enter.into_node() {
+                    Some(node) => node,
+                    None => continue,
+                };
+                if let Some(set) = AttrSet::cast(node.clone()) {
+                    results.extend(visit_entries(options, &set));
+                } else if let Some(let_in) = LetIn::cast(node.clone()) {
+                    results.extend(visit_entries(options, &let_in));
+                } else if let Some(legacy_let) = LegacyLet::cast(node) {
+                    results.extend(visit_entries(options, &legacy_let));
                 }
             }
             WalkEvent::Leave(_leave) => {
@@ -194,17 +963,42 @@ fn search_ast(identifier: &Regex, ast: &AST) -> Vec<SearchResult> {
     results
 }
 
+/// Looks at the whole file as a function if it is one. Its name is filled in by [`search_file`].
+fn visit_file_function(options: &SearchOptions, ast: &AST) -> Option<SearchResult> {
+    let lambda = ast.root().inner().and_then(Lambda::cast)?;
+    let start = lambda.node().text_range().start().to_usize();
+    let mut res = visit_lambda(String::new(), start, &lambda);
+    if !res.is_documented() && !options.include_undocumented || !options.takes(&res) {
+        return None;
+    }
+    res.binding = BindingKind::File;
+    Some(res)
+}
+
 /// Emits a string `s` indented by `indent` spaces
 fn indented(s: &str, indent: usize) -> String {
-    let indent_s = iter::repeat(' ').take(indent).collect::<String>();
+    let indent_s = " ".repeat(indent);
     s.split('\n')
         .map(|line| indent_s.clone() + line)
         .collect::<Vec<_>>()
         .join("\n")
 }
 
+/// Highlights the given byte `ranges` of `s`
+fn highlighted(s: &str, ranges: &[Range<usize>], options: &FormatOptions) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for range in ranges {
+        out.push_str(&s[last..range.start]);
+        out.push_str(&options.paint(&s[range.clone()], |s| s.yellow().bold()));
+        last = range.end;
+    }
+    out.push_str(&s[last..]);
+    out
+}
+
 /// Cleans up a single line, erasing prefix single line comments but preserving indentation
-fn cleanup_single_line<'a>(s: &'a str) -> &'a str {
+fn cleanup_single_line(s: &str) -> &str {
     let mut cmt_new_start = 0;
     let mut iter = s.char_indices().peekable();
     while let Some((idx, ch)) = iter.next() {
@@ -297,13 +1091,53 @@ fn cleanup_comments<S: AsRef<str>, I: DoubleEndedIterator<Item = S>>(comment: &m
     )
 }
 
-/// Get the docs for a specific function
-pub fn get_function_docs(filename: &str, line: usize, col: usize) -> Option<String> {
-    let content = fs::read(filename).ok()?;
-    let decoded = str::from_utf8(&content).ok()?;
-    let pos = find_pos(&decoded, line, col);
+/// Is this an [RFC 145](https://github.com/NixOS/rfcs/pull/145) `/** ... */` doc comment?
+fn is_doc_comment(comment: &str) -> bool {
+    comment.starts_with("/**") && comment != "/**/"
+}
+
+/// Takes the Markdown body out of an RFC 145 doc comment. Unlike [`cleanup_comments`], this leaves
+/// the contents alone apart from removing the common indent, so `*` list items survive.
+fn cleanup_doc_comment(comment: &str) -> String {
+    let body = comment
+        .trim_start_matches("/**")
+        .trim_end_matches("*/")
+        .trim_end();
+    // skip blank lines after the opener but keep the indent of the first line with text on it, so
+    // it is dedented the same as the rest
+    let first_text = body
+        .find(|ch: char| !ch.is_whitespace())
+        .unwrap_or(body.len());
+    let start = body[..first_text].rfind('\n').map_or(0, |nl| nl + 1);
+    dedent_comment(&body[start..])
+}
+
+/// Get the docs for the function defined at the given 1-based line and column of a file. Like in
+/// the positions Nix reports, the column is counted in bytes.
+pub fn get_function_docs(
+    filename: &str,
+    line: usize,
+    col: usize,
+    options: &FormatOptions,
+) -> Result<String> {
+    let content = fs::read(filename)?;
+    get_function_docs_from_source(str::from_utf8(&content)?, filename, line, col, options)
+}
+
+/// Get the docs for the function defined at the given 1-based line and column of `source`, which
+/// does not have to be a file on disk: it can be an `--expr` string or an unsaved editor buffer.
+/// `name` is shown where the file name would be. The column is counted in bytes.
+pub fn get_function_docs_from_source(
+    source: &str,
+    name: &str,
+    line: usize,
+    col: usize,
+    options: &FormatOptions,
+) -> Result<String> {
+    let index = LineIndex::new(source);
+    let pos = index.offset(line, col, ColumnUnit::Byte)?;
     let rowan_pos = TextUnit::from_usize(pos);
-    let tree = rnix::parse(decoded);
+    let tree = rnix::parse(source);
 
     let mut lambda = None;
     for node in tree.node().preorder() {
@@ -317,27 +1151,68 @@ pub fn get_function_docs(filename: &str, line: usize, col: usize) -> Option<Stri
             WalkEvent::Leave(_) => (),
         }
     }
-    let lambda = lambda?;
-    let res = visit_lambda("func".to_string(), pos, &lambda);
-    Some(res.format(filename, line))
+    let lambda = match lambda {
+        Some(lambda) => lambda,
+        // the function may well be hidden in a part of the file that doesn't parse
+        None => match tree.errors().first() {
+            Some(err) => return Err(ParseDiagnostic::new(Path::new(name), &index, err)?.into()),
+            None => return Err(Error::NoDefinition { line, column: col }),
+        },
+    };
+    let (path, binding) = match lambda_binding(&lambda) {
+        Some(found) => found,
+        None if is_file_function(&tree, &lambda) && is_searchable(Path::new(name)) => {
+            (vec![file_function_name(Path::new(name))], BindingKind::File)
+        }
+        None => (vec!["<lambda>".to_string()], BindingKind::Attribute),
+    };
+    let identifier = path.last().cloned().unwrap_or_default();
+    let mut res = visit_lambda(identifier, pos, &lambda);
+    res.path = path.join(".");
+    res.binding = binding;
+    res.file = PathBuf::from(name);
+    res.line = line;
+    // search results count columns in characters
+    res.column = index.position(pos, ColumnUnit::Char)?.1;
+    Ok(res.format(options))
 }
 
 fn visit_lambda(name: String, defined_at_start: usize, lambda: &Lambda) -> SearchResult {
     // grab the arguments
-    let param_block = pprint_args(&lambda);
+    let signature = Signature::of(lambda);
+    let pattern = signature.pattern_info();
 
     // find the doc comment
-    let comment = find_comment(lambda.node().clone()).unwrap_or_else(|| "".to_string());
+    let (comment, doc_format) = find_comment(lambda.node().clone()).unwrap_or_default();
 
     SearchResult {
+        path: name.clone(),
         identifier: name,
+        sections: DocSections::parse(&comment),
         doc: comment,
-        param_block,
+        doc_format,
+        doc_matches: Vec::new(),
+        param_block: signature.render(None),
+        arguments: signature.formal_docs(),
+        signature,
+        pattern,
+        binding: BindingKind::Attribute,
+        quality: MatchQuality::Exact,
+        type_match: None,
+        root: String::new(),
+        file: PathBuf::new(),
+        depth: 0,
+        line: 0,
+        column: 0,
         defined_at_start,
+        definition: lambda.node().text_range().start().to_usize()
+            ..lambda.node().text_range().end().to_usize(),
+        source: None,
     }
 }
 
-fn visit_attrset(id_needle: &Regex, set: &AttrSet) -> Vec<SearchResult> {
+/// Finds documented functions among the `key = value;` entries of an attrset or `let` block
+fn visit_entries<E: EntryHolder>(options: &SearchOptions, set: &E) -> Vec<SearchResult> {
     let mut results = Vec::new();
     for entry in set.entries() {
         if let Some(lambda) = entry.value().and_then(Lambda::cast) {
@@ -347,25 +1222,163 @@ fn visit_attrset(id_needle: &Regex, set: &AttrSet) -> Vec<SearchResult> {
                     .as_ref()
                     .map(|i| i.node().text_range().start().to_usize());
 
-                let ident_name = ident.as_ref().map(|id| id.as_str());
+                let ident_name = match ident.as_ref() {
+                    Some(id) => id.as_str(),
+                    None => continue,
+                };
+                let (path, binding) = attr_path(&entry);
+                let path = path.join(".");
+
+                if options.target == SearchTarget::Name
+                    && !options
+                        .matching
+                        .iter()
+                        .all(|re| match_quality(re, &[ident_name, &path]).is_some())
+                {
+                    // rejected, not matching our pattern. Checked early since this is the common
+                    // case and it avoids looking for doc comments at all
+                    continue;
+                }
+
+                let mut res =
+                    visit_lambda(ident_name.to_string(), defined_at_start.unwrap(), &lambda);
+                res.definition.start = entry.node().text_range().start().to_usize();
+                if !res.is_documented() && !options.include_undocumented {
+                    continue;
+                }
 
-                if ident_name.map(|id| id_needle.is_match(id)) != Some(true) {
-                    // rejected, not matching our pattern
+                if !options.takes(&res) {
                     continue;
                 }
-                let ident_name = ident_name.unwrap();
 
-                let res = visit_lambda(ident_name.to_string(), defined_at_start.unwrap(), &lambda);
-                if !res.doc.is_empty() {
-                    results.push(res);
+                if let Some(query) = &options.type_query {
+                    let declared = res
+                        .sections
+                        .type_sig
+                        .as_deref()
+                        .and_then(|sig| Type::parse_signature(sig).ok());
+                    res.type_match = match declared.and_then(|ty| query.matches(&ty)) {
+                        Some(type_match) => Some(type_match),
+                        None => continue,
+                    };
                 }
+
+                res.quality = match options.quality(&[ident_name, &path], &res.doc) {
+                    Some(quality) => quality,
+                    None => continue,
+                };
+
+                res.doc_matches = options.doc_matches(&res.doc);
+                res.path = path;
+                res.binding = binding;
+                results.push(res);
             }
         }
     }
     results
 }
 
-fn find_comment(node: SyntaxNode) -> Option<String> {
+/// Finds the best way `re` matches any of the `names`
+fn match_quality(re: &Regex, names: &[&str]) -> Option<MatchQuality> {
+    names
+        .iter()
+        .flat_map(|name| {
+            re.find_iter(name).map(move |m| {
+                if m.range() == (0..name.len()) {
+                    MatchQuality::Exact
+                } else if m.start() == 0 {
+                    MatchQuality::Prefix
+                } else {
+                    MatchQuality::Substring
+                }
+            })
+        })
+        .min()
+}
+
+/// Renders one component of an attribute key the way it is written in the source, so that
+/// string and dynamic keys keep their quotes and `${}`.
+fn key_component(node: &SyntaxNode) -> String {
+    match Ident::cast(node.clone()) {
+        Some(ident) => ident.as_str().to_string(),
+        None => node.to_string(),
+    }
+}
+
+/// Finds the attribute path and kind of the binding that `lambda` is the value of, looking through
+/// parentheses, the bodies of `with`, `assert` and `let` around it, and the functions it is the
+/// body of when curried.
+fn lambda_binding(lambda: &Lambda) -> Option<(Vec<String>, BindingKind)> {
+    let mut value = enclosing_value(lambda.node().clone());
+    while let Some(outer) = value.parent().and_then(Lambda::cast) {
+        if outer.body().as_ref() != Some(&value) {
+            break;
+        }
+        value = enclosing_value(outer.node().clone());
+    }
+    let entry = KeyValue::cast(value.parent()?)?;
+    let (path, binding) = attr_path(&entry);
+    if path.is_empty() {
+        None
+    } else {
+        Some((path, binding))
+    }
+}
+
+/// Climbs out of any parentheses around `node` and any `with`, `assert` and `let` it is the body
+/// of, to the expression that `node` is the value of
+fn enclosing_value(node: SyntaxNode) -> SyntaxNode {
+    let mut value = node;
+    while let Some(parent) = value.parent() {
+        let body = match parent.kind() {
+            NODE_PAREN => Some(value.clone()),
+            NODE_WITH => With::cast(parent.clone()).and_then(|with| with.body()),
+            NODE_ASSERT => Assert::cast(parent.clone()).and_then(|assert| assert.body()),
+            NODE_LET_IN => LetIn::cast(parent.clone()).and_then(|let_in| let_in.body()),
+            _ => None,
+        };
+        if body.as_ref() != Some(&value) {
+            break;
+        }
+        value = parent;
+    }
+    value
+}
+
+/// Is `lambda` the whole file, like the package functions given to `callPackage`?
+fn is_file_function(ast: &AST, lambda: &Lambda) -> bool {
+    ast.root().inner().as_ref() == Some(lambda.node())
+}
+
+/// Finds the full attribute path of `entry`, including the keys of the attrsets enclosing it, and
+/// whether the outermost binding of that path is a `let` binding.
+fn attr_path(entry: &KeyValue) -> (Vec<String>, BindingKind) {
+    let mut path = Vec::new();
+    let mut entry = entry.clone();
+    loop {
+        if let Some(key) = entry.key() {
+            let components = key.path().map(|n| key_component(&n)).collect::<Vec<_>>();
+            path.splice(0..0, components);
+        }
+
+        let holder = match entry.node().parent() {
+            Some(holder) => holder,
+            None => return (path, BindingKind::Attribute),
+        };
+        match holder.kind() {
+            NODE_LET_IN | NODE_LEGACY_LET => return (path, BindingKind::Local),
+            NODE_ATTR_SET => (),
+            _ => return (path, BindingKind::Attribute),
+        }
+
+        entry = match enclosing_value(holder).parent().and_then(KeyValue::cast) {
+            Some(parent) => parent,
+            None => return (path, BindingKind::Attribute),
+        };
+    }
+}
+
+fn find_comment(node: SyntaxNode) -> Option<(String, DocFormat)> {
     let mut node = NodeOrToken::Node(node);
     let mut comments = Vec::new();
     loop {
@@ -390,18 +1403,100 @@ fn find_comment(node: SyntaxNode) -> Option<String> {
             _ => break,
         }
     }
-    let doc = cleanup_comments(&mut comments.iter().map(|c| c.as_str()));
-    Some(doc).filter(|it| !it.is_empty())
+    // an RFC 145 doc comment is authoritative: anything else above it is just a comment
+    let doc = match comments.iter().find(|c| is_doc_comment(c)) {
+        Some(doc_comment) => (cleanup_doc_comment(doc_comment), DocFormat::Markdown),
+        None => (
+            cleanup_comments(&mut comments.iter().map(|c| c.as_str())),
+            DocFormat::Plain,
+        ),
+    };
+    Some(doc).filter(|(it, _)| !it.is_empty())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Functions around a syntax error
+    const BROKEN_NIX: &str = r#"{
+  # Still found even though the file has a syntax error further down
+  intact = a: b: a + b;
+
+  broken = x: ) x;
+
+  # Also found, after the error
+  after = { y }: y;
+}"#;
+
+    /// A package, which is a function of the whole file
+    const PACKAGE_NIX: &str = r#"{ lib
+, stdenv
+, fetchFromGitHub
+, enableFoo ? false
+, ...
+}@args:
+
+stdenv.mkDerivation {
+  pname = "hello";
+
+  passthru = {
+    # Builds a helper with the same stdenv
+    builder = { stdenv, src ? null }: stdenv.mkDerivation { inherit src; };
+  };
+}"#;
+
+    fn search_package(options: &SearchOptions) -> Vec<SearchResult> {
+        search_source(Path::new("package.nix"), PACKAGE_NIX, options)
+            .unwrap()
+            .0
+    }
+
+    /// Functions bound by `let` and nested in attrsets
+    const LET_NIX: &str = r#"let
+  # Adds one to a number
+  addOne = x: x + 1;
+
+  addUndocumented = x: x;
+
+  z = {
+    "with.dots" = {
+      # a function nested in a local attrset
+      nested = a: a;
+    };
+  };
+in
+rec {
+  /* Adds two to a number */
+  addTwo = x: addOne (addOne x);
+
+  three = let {
+    # Adds three to a number
+    addThree = x: addTwo (addOne x);
+    body = addThree;
+  };
+
+  strings = {
+    # Pads a string to the given width
+    fixedWidth = width: str: str;
+  };
+}"#;
+
     #[test]
     fn test_bytepos() {
-        let fakefile = "abc\ndef\nghi";
-        assert_eq!(find_pos(fakefile, 2, 2), 5);
+        let fakefile = LineIndex::new("abc\ndef\nghi");
+        assert_eq!(fakefile.offset(2, 2, ColumnUnit::Byte).unwrap(), 5);
+        assert!(matches!(
+            fakefile.offset(4, 1, ColumnUnit::Byte),
+            Err(Error::Position { line: 4, column: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_column() {
+        let fakefile = LineIndex::new("abc\ndéf\nghi");
+        assert_eq!(fakefile.position(0, ColumnUnit::Char).unwrap(), (1, 1));
+        assert_eq!(fakefile.position(7, ColumnUnit::Char).unwrap(), (2, 3));
     }
 
     #[test]
@@ -444,6 +1539,489 @@ mod tests {
         assert_eq!(cleanup_single_line(ex1), ex1);
     }
 
+    #[test]
+    fn test_search_file_locations() {
+        let results = search_file(
+            Path::new("testdata/test.nix"),
+            &SearchOptions::new(Regex::new("the-snd-fn").unwrap()),
+        )
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file, Path::new("testdata/test.nix"));
+        assert_eq!((results[0].line, results[0].column), (14, 4));
+    }
+
+    #[test]
+    fn test_summary_location() {
+        let mut results = search_file(
+            Path::new("testdata/test.nix"),
+            &SearchOptions::new(Regex::new("the-snd-fn").unwrap()),
+        )
+        .unwrap();
+        results[0].root = "<td>".to_string();
+        let options = FormatOptions {
+            summary: true,
+            ..FormatOptions::plain()
+        };
+        let summary = results[0].format(&options);
+        assert!(summary.ends_with("  # testdata/test.nix:14"), "{}", summary);
+        let summary = results[0].format(&FormatOptions {
+            show_root: true,
+            ..options
+        });
+        assert!(summary.ends_with("  # testdata/test.nix:14 (in <td>)"));
+        assert_eq!(summary.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let src = r#"{
+  # Not the docs
+  /* Also not the docs */
+  /**
+    Does *things*.
+
+    * one
+    * two
+
+    ```nix
+    f 1
+    ```
+  */
+  f = x: x;
+
+  /** Short and sweet */
+  g = x: x;
+}"#;
+        let ast = rnix::parse(src).as_result().unwrap();
+        let results = search_ast(&SearchOptions::new(Regex::new("").unwrap()), &ast);
+        let docs = results.iter().map(|r| r.doc.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            docs,
+            [
+                "Does *things*.\n\n* one\n* two\n\n```nix\nf 1\n```",
+                "Short and sweet",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_type_search() {
+        let ast = rnix::parse(include_str!("../testdata/regression-11.nix"))
+            .as_result()
+            .unwrap();
+        let options = SearchOptions::by_type(Type::parse("string -> int -> string").unwrap());
+        let results = search_ast(&options, &ast);
+        let found = results
+            .iter()
+            .map(|r| (r.identifier.as_str(), r.type_match))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [("fixedWidthString", Some(TypeMatch::Reordered))]);
+    }
+
+    #[test]
+    fn test_formals_search() {
+        let options = SearchOptions::by_formals(vec!["stdenv".to_string()]);
+        let results = search_package(&options);
+        let found = results
+            .iter()
+            .map(|r| (r.path.as_str(), r.binding, r.pattern.clone().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (
+                    "package",
+                    BindingKind::File,
+                    PatternInfo {
+                        formals: vec![
+                            "lib".to_string(),
+                            "stdenv".to_string(),
+                            "fetchFromGitHub".to_string(),
+                            "enableFoo".to_string(),
+                        ],
+                        defaults: vec!["enableFoo".to_string()],
+                        ellipsis: true,
+                        bind: Some("args".to_string()),
+                    }
+                ),
+                (
+                    "passthru.builder",
+                    BindingKind::Attribute,
+                    PatternInfo {
+                        formals: vec!["stdenv".to_string(), "src".to_string()],
+                        defaults: vec!["src".to_string()],
+                        ellipsis: false,
+                        bind: None,
+                    }
+                ),
+            ]
+        );
+
+        let options = SearchOptions::by_formals(vec!["stdenv".to_string(), "lib".to_string()]);
+        let results = search_package(&options);
+        assert_eq!(results.len(), 1);
+
+        // a regex narrows the results down by name, including the name of the file
+        let options = SearchOptions {
+            matching: vec![Regex::new("^pack").unwrap()],
+            ..SearchOptions::by_formals(vec!["stdenv".to_string()])
+        };
+        let results = search_package(&options);
+        let found = results
+            .iter()
+            .map(|r| (r.path.as_str(), r.quality))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [("package", MatchQuality::Prefix)]);
+        assert_eq!(
+            file_function_name(Path::new("pkgs/hello/default.nix")),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn test_arguments() {
+        let results = search_file(
+            Path::new("testdata/test.nix"),
+            &SearchOptions::new(Regex::new("the-snd-fn").unwrap()),
+        )
+        .unwrap();
+        assert_eq!(
+            results[0].arguments,
+            [FormalDoc {
+                name: "c".to_string(),
+                doc: "doc".to_string(),
+            }]
+        );
+        let formatted = results[0].format(&FormatOptions::plain());
+        assert!(formatted.contains("   Arguments:\n     c  doc\n"));
+    }
+
+    #[test]
+    fn test_source() {
+        let options = SearchOptions {
+            formals: vec!["stdenv".to_string()],
+            source: true,
+            ..SearchOptions::by_formals(Vec::new())
+        };
+        let results = search_package(&options);
+        let builder = results
+            .iter()
+            .find(|r| r.path == "passthru.builder")
+            .unwrap();
+        assert_eq!(
+            builder.source.as_deref(),
+            Some("builder = { stdenv, src ? null }: stdenv.mkDerivation { inherit src; }")
+        );
+
+        let package = &results[0];
+        assert_eq!((package.line, package.column), (1, 1));
+        assert!(package
+            .source
+            .as_ref()
+            .unwrap()
+            .starts_with("{ lib\n, stdenv\n"));
+        let formatted = package.format(&FormatOptions {
+            source_lines: Some(2),
+            ..FormatOptions::plain()
+        });
+        assert!(formatted.ends_with("\n\n   { lib\n   , stdenv\n   … 13 more lines"));
+    }
+
+    #[test]
+    fn test_let_bindings() {
+        let ast = rnix::parse(LET_NIX).as_result().unwrap();
+        let results = search_ast(&SearchOptions::new(Regex::new("^add").unwrap()), &ast);
+        let found = results
+            .iter()
+            .map(|r| (r.identifier.as_str(), r.binding))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("addOne", BindingKind::Local),
+                ("addTwo", BindingKind::Attribute),
+                ("addThree", BindingKind::Local),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_include_undocumented() {
+        let ast = rnix::parse(LET_NIX).as_result().unwrap();
+        let mut options = SearchOptions::new(Regex::new("Undocumented").unwrap());
+        assert!(search_ast(&options, &ast).is_empty());
+
+        options.include_undocumented = true;
+        let results = search_ast(&options, &ast);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].identifier, "addUndocumented");
+        assert_eq!(results[0].doc, "");
+        assert_eq!(results[0].param_block, "x: ...");
+    }
+
+    #[test]
+    fn test_doc_search() {
+        let ast = rnix::parse(include_str!("../testdata/regression-11.nix"))
+            .as_result()
+            .unwrap();
+        let mut options = SearchOptions::keywords("autoconf WITH");
+        assert!(search_ast(&options, &ast).is_empty());
+
+        options.target = SearchTarget::Doc;
+        let results = search_ast(&options, &ast);
+        let found = results
+            .iter()
+            .map(|r| r.identifier.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["withFeature", "withFeatureAs"]);
+        let doc = &results[0].doc;
+        let matched = results[0]
+            .doc_matches
+            .iter()
+            .map(|r| &doc[r.clone()])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matched,
+            ["with", "with", "Autoconf", "with", "with", "with", "with"]
+        );
+
+        let mut options = SearchOptions::new(Regex::new("^fixed|pad").unwrap());
+        options.target = SearchTarget::Both;
+        let results = search_ast(&options, &ast);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].identifier, "fixedWidthString");
+        assert!(results[0].doc_matches.is_empty());
+    }
+
+    #[test]
+    fn test_search_errors() {
+        let dir = env::temp_dir().join(format!("nix-doc-search-errors-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("latin1.nix"), b"{ caf\xe9 = x: x; }").unwrap();
+
+        let mut found = search(
+            &[SearchRoot::new(dir.clone())],
+            &SearchOptions::new(Regex::new("").unwrap()),
+            &WalkOptions::default(),
+        );
+        assert_eq!(found.by_ref().count(), 0);
+        let errors = found.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, dir.join("latin1.nix"));
+        assert!(matches!(errors[0].error, Error::Utf8(_)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_roots() {
+        let roots = [
+            SearchRoot {
+                name: "<td>".to_string(),
+                path: PathBuf::from("testdata"),
+            },
+            SearchRoot::new(PathBuf::from("testdata/ignore")),
+        ];
+        let walk = WalkOptions {
+            no_ignore: true,
+            ..WalkOptions::default()
+        };
+        let mut results = search(
+            &roots,
+            &SearchOptions::new(Regex::new("^generated$").unwrap()),
+            &walk,
+        )
+        .collect::<Vec<_>>();
+        sort_results(&mut results, SortOrder::Relevance);
+        let found = results
+            .iter()
+            .map(|r| (r.root.as_str(), r.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [("testdata/ignore", 0), ("<td>", 1)]);
+    }
+
+    #[test]
+    fn test_sort_relevance() {
+        let options = SearchOptions::new(Regex::new("fixedWidth").unwrap());
+        let ast = rnix::parse(LET_NIX).as_result().unwrap();
+        let mut results = search_ast(&options, &ast);
+        results.extend(search_file(Path::new("testdata/regression-11.nix"), &options).unwrap());
+
+        sort_results(&mut results, SortOrder::Relevance);
+        let found = results
+            .iter()
+            .map(|r| (r.path.as_str(), r.quality))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("strings.fixedWidth", MatchQuality::Exact),
+                ("fixedWidthString", MatchQuality::Prefix),
+            ]
+        );
+
+        sort_results(&mut results, SortOrder::Path);
+        assert_eq!(results[0].identifier, "fixedWidth");
+        assert_eq!(results[1].identifier, "fixedWidthString");
+
+        let mut options = SearchOptions::new(Regex::new("GNU|Width").unwrap());
+        options.target = SearchTarget::Both;
+        let mut results = search_file(Path::new("testdata/regression-11.nix"), &options).unwrap();
+        sort_results(&mut results, SortOrder::Relevance);
+        let found = results
+            .iter()
+            .map(|r| (r.path.as_str(), r.quality))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("fixedWidthString", MatchQuality::Substring),
+                ("withFeature", MatchQuality::Doc),
+                ("withFeatureAs", MatchQuality::Doc),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_attr_paths() {
+        let ast = rnix::parse(LET_NIX).as_result().unwrap();
+        let results = search_ast(
+            &SearchOptions::new(Regex::new(r"^strings\.fixed").unwrap()),
+            &ast,
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].identifier, "fixedWidth");
+        assert_eq!(results[0].path, "strings.fixedWidth");
+        assert_eq!(results[0].binding, BindingKind::Attribute);
+
+        let results = search_ast(&SearchOptions::new(Regex::new(r"nested").unwrap()), &ast);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, r#"z."with.dots".nested"#);
+        assert_eq!(results[0].binding, BindingKind::Local);
+
+        let ast = rnix::parse(include_str!("../testdata/test.nix"))
+            .as_result()
+            .unwrap();
+        let results = search_ast(&SearchOptions::new(Regex::new(r"^a\.b\.c$").unwrap()), &ast);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].identifier, "c");
+        assert_eq!(results[0].path, "a.b.c");
+
+        let src = r#"{
+          strings = with builtins; { pad = x: x; };
+          other = let y = 1; in assert true; { thing = x: x; };
+          ns = with { notHere = x: x; }; 1;
+        }"#;
+        let ast = rnix::parse(src).as_result().unwrap();
+        let options = SearchOptions {
+            include_undocumented: true,
+            ..SearchOptions::new(Regex::new("").unwrap())
+        };
+        let paths = search_ast(&options, &ast)
+            .into_iter()
+            .map(|r| r.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["strings.pad", "other.thing", "notHere"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let (results, errors) = search_source(
+            Path::new("broken.nix"),
+            BROKEN_NIX,
+            &SearchOptions::new(Regex::new("").unwrap()),
+        )
+        .unwrap();
+        let found = results
+            .iter()
+            .map(|r| r.identifier.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["intact", "after"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (5, 15));
+        assert!(errors[0]
+            .message
+            .starts_with("unexpected TOKEN_PAREN_CLOSE"));
+    }
+
+    #[test]
+    fn test_function_docs_errors() {
+        let options = FormatOptions::plain();
+        let docs = |file: &str, line, col| get_function_docs(file, line, col, &options);
+        assert!(docs("testdata/test.nix", 9, 4)
+            .unwrap()
+            .contains("a: b: ..."));
+        assert!(matches!(
+            docs("testdata/test.nix", 33, 2),
+            Err(Error::NoDefinition {
+                line: 33,
+                column: 2
+            })
+        ));
+        assert!(matches!(
+            docs("testdata/test.nix", 100, 1),
+            Err(Error::Position { .. })
+        ));
+        assert!(matches!(
+            get_function_docs_from_source(BROKEN_NIX, "broken.nix", 9, 1, &options),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            docs("testdata/missing.nix", 1, 1),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_function_docs_names() {
+        let options = FormatOptions::plain();
+        let signature = |file: &str, line, col| {
+            let docs = get_function_docs(file, line, col, &options).unwrap();
+            docs.lines().nth_back(1).unwrap().to_string()
+        };
+        assert_eq!(signature("testdata/test.nix", 17, 4), "a.b.c = a: ...");
+        let let_signature = |line, col| {
+            let docs = get_function_docs_from_source(LET_NIX, "let.nix", line, col, &options);
+            docs.unwrap().lines().nth_back(1).unwrap().to_string()
+        };
+        assert_eq!(let_signature(3, 3), "let addOne = x: ...");
+        assert_eq!(let_signature(10, 7), r#"let z."with.dots".nested = a: ..."#);
+        assert_eq!(
+            signature("testdata/test.nix", 1, 1),
+            "test = { parm1, parm2 }: ..."
+        );
+
+        let anonymous =
+            get_function_docs_from_source("map (x: x) [ ]", "«string»", 1, 1, &options).unwrap();
+        assert!(anonymous.contains("\n<lambda> = x: ...\n"));
+
+        // the body of a curried function is named after the outer one
+        let curried = get_function_docs_from_source(
+            "{ a = x: (y: x); }",
+            "«string»",
+            1,
+            "{ a = x: (".len() + 1,
+            &options,
+        )
+        .unwrap();
+        assert!(curried.contains("\na = y: ...\n"));
+    }
+
+    #[test]
+    fn test_function_docs_from_source() {
+        let source = "{\n  # Adds ünïcode\n  add = a: b: a + b;\n}\n";
+        let docs = get_function_docs_from_source(
+            source,
+            "«string»",
+            3,
+            "  add = ".len() + 1,
+            &FormatOptions::plain(),
+        )
+        .unwrap();
+        assert!(docs.contains("Adds ünïcode"));
+        assert!(docs.ends_with("# «string»:3"));
+    }
+
     #[test]
     fn test_regression_11() {
         let out = r#"Create a fixed width string with additional prefix to match
@@ -460,9 +2038,16 @@ Example:
         let ast = rnix::parse(include_str!("../testdata/regression-11.nix"))
             .as_result()
             .unwrap();
-        let results = search_ast(&regex::Regex::new("fixedWidthString").unwrap(), &ast);
+        let results = search_ast(
+            &SearchOptions::new(Regex::new("fixedWidthString").unwrap()),
+            &ast,
+        );
         assert_eq!(results.len(), 1);
 
         assert_eq!(results[0].doc, out);
+        assert_eq!(
+            results[0].sections.type_sig.as_deref(),
+            Some("fixedWidthString :: int -> string -> string")
+        );
     }
 }
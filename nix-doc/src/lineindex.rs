@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Mapping between byte offsets and line/column positions in a file
+use std::iter;
+use std::ops::Range;
+
+use crate::{Error, Result};
+
+/// What the columns of a position count
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnUnit {
+    /// Bytes of UTF-8, which is how Nix counts columns
+    Byte,
+    /// Unicode scalar values, which is how people usually count columns
+    Char,
+    /// UTF-16 code units, which is how the language server protocol counts columns
+    Utf16,
+}
+
+impl ColumnUnit {
+    fn of_char(self, ch: char) -> usize {
+        match self {
+            ColumnUnit::Byte => ch.len_utf8(),
+            ColumnUnit::Char => 1,
+            ColumnUnit::Utf16 => ch.len_utf16(),
+        }
+    }
+
+    fn of_str(self, s: &str) -> usize {
+        s.chars().map(|ch| self.of_char(ch)).sum()
+    }
+}
+
+/// The starts of the lines of a file, for mapping byte offsets to 1-based lines and columns and
+/// back
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// Byte offset of the start of every line
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let starts = iter::once(0)
+            .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        LineIndex { text, starts }
+    }
+
+    /// The indexed text
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Number of lines, including the empty line after a trailing newline
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// 1-based line containing the byte `offset`
+    pub fn line(&self, offset: usize) -> Result<usize> {
+        if !self.text.is_char_boundary(offset) {
+            return Err(Error::Offset { offset });
+        }
+        Ok(self.starts.partition_point(|&start| start <= offset))
+    }
+
+    /// 1-based line and column of the byte `offset`, with the column counted in `unit`s
+    pub fn position(&self, offset: usize, unit: ColumnUnit) -> Result<(usize, usize)> {
+        let line = self.line(offset)?;
+        let before = &self.text[self.starts[line - 1]..offset];
+        Ok((line, unit.of_str(before) + 1))
+    }
+
+    /// Byte offset of the 1-based `line` and `column`, with the column counted in `unit`s. The
+    /// column can be just past the end of the line.
+    pub fn offset(&self, line: usize, column: usize, unit: ColumnUnit) -> Result<usize> {
+        let bad = || Error::Position { line, column };
+        let range = self.line_range(line).ok_or_else(bad)?;
+        let wanted = column.checked_sub(1).ok_or_else(bad)?;
+        let mut seen = 0;
+        for (idx, ch) in self.text[range.clone()].char_indices() {
+            if seen == wanted {
+                return Ok(range.start + idx);
+            } else if seen > wanted {
+                // in the middle of a character
+                return Err(bad());
+            }
+            seen += unit.of_char(ch);
+        }
+        if seen == wanted {
+            Ok(range.end)
+        } else {
+            Err(bad())
+        }
+    }
+
+    /// Text of the 1-based `line`, without its line ending
+    pub fn line_text(&self, line: usize) -> Result<&'a str> {
+        let range = self
+            .line_range(line)
+            .ok_or(Error::Position { line, column: 1 })?;
+        Ok(&self.text[range])
+    }
+
+    /// Byte range of the 1-based `line`, without its line ending
+    fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .starts
+            .get(line)
+            .map_or(self.text.len(), |next| next - 1);
+        Some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let index = LineIndex::new("abc\ndéf\n𝄞x\n");
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line(0).unwrap(), 1);
+        assert_eq!(index.line(3).unwrap(), 1);
+        assert_eq!(index.line(4).unwrap(), 2);
+        assert_eq!(index.position(0, ColumnUnit::Char).unwrap(), (1, 1));
+        // the f in déf
+        assert_eq!(index.position(7, ColumnUnit::Byte).unwrap(), (2, 4));
+        assert_eq!(index.position(7, ColumnUnit::Char).unwrap(), (2, 3));
+        // the x after the clef, which is 4 bytes and a surrogate pair
+        assert_eq!(index.position(13, ColumnUnit::Byte).unwrap(), (3, 5));
+        assert_eq!(index.position(13, ColumnUnit::Char).unwrap(), (3, 2));
+        assert_eq!(index.position(13, ColumnUnit::Utf16).unwrap(), (3, 3));
+        assert_eq!(index.position(15, ColumnUnit::Char).unwrap(), (4, 1));
+        assert!(matches!(index.line(6), Err(Error::Offset { offset: 6 })));
+        assert!(matches!(index.line(16), Err(Error::Offset { offset: 16 })));
+    }
+
+    #[test]
+    fn offsets() {
+        let index = LineIndex::new("abc\ndéf\n𝄞x");
+        assert_eq!(index.offset(2, 2, ColumnUnit::Char).unwrap(), 5);
+        assert_eq!(index.offset(2, 3, ColumnUnit::Char).unwrap(), 7);
+        assert_eq!(index.offset(2, 4, ColumnUnit::Byte).unwrap(), 7);
+        assert_eq!(index.offset(3, 3, ColumnUnit::Utf16).unwrap(), 13);
+        // just past the end of a line
+        assert_eq!(index.offset(1, 4, ColumnUnit::Char).unwrap(), 3);
+        assert_eq!(index.offset(3, 3, ColumnUnit::Char).unwrap(), 14);
+
+        for (line, column, unit) in [
+            (0, 1, ColumnUnit::Char),
+            (4, 1, ColumnUnit::Char),
+            (1, 0, ColumnUnit::Char),
+            (1, 6, ColumnUnit::Char),
+            // inside the é and the clef
+            (2, 3, ColumnUnit::Byte),
+            (3, 2, ColumnUnit::Utf16),
+        ] {
+            assert!(
+                matches!(
+                    index.offset(line, column, unit),
+                    Err(Error::Position { .. })
+                ),
+                "{}:{} {:?}",
+                line,
+                column,
+                unit
+            );
+        }
+    }
+
+    #[test]
+    fn line_text() {
+        let index = LineIndex::new("abc\ndéf\n");
+        assert_eq!(index.line_text(1).unwrap(), "abc");
+        assert_eq!(index.line_text(2).unwrap(), "déf");
+        assert_eq!(index.line_text(3).unwrap(), "");
+        assert!(index.line_text(4).is_err());
+    }
+}
@@ -4,24 +4,166 @@
 
 //! A nix documentation search program
 
-use nix_doc::{is_searchable, search, tags, Result};
+use nix_doc::nixpath::{self, SearchPath};
+use nix_doc::types::Type;
+use nix_doc::walk::{glob_set, WalkOptions};
+use nix_doc::{
+    search, sort_results, tags, ColorChoice, Error, FormatOptions, SearchOptions, SearchResult,
+    SearchRoot, SearchTarget, SortOrder, MAX_FILE_SIZE,
+};
 
+use colorful::{Color, Colorful};
 use regex::Regex;
 use structopt::StructOpt;
 
-use std::{fs, io::BufWriter, path::PathBuf};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::{fs, io::BufWriter};
+
+/// Errors of the CLI itself, like bad arguments, besides those from the library
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// How search results are written out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human readable, coloured text
+    Text,
+    /// A single JSON array of hits
+    Json,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "json-lines" => Ok(OutputFormat::JsonLines),
+            _ => Err(format!(
+                "unknown output format {:?}, expected one of: text, json, jsonl",
+                s
+            )),
+        }
+    }
+}
+
+/// Options selecting which files are looked at
+#[derive(StructOpt, Debug)]
+struct WalkArgs {
+    /// Skip files larger than this many bytes. Pass 0 to disable the limit. Defaults to 200000
+    /// for search and no limit for tags.
+    #[structopt(long)]
+    max_file_size: Option<u64>,
+
+    /// Only look at files matching this glob. Can be given multiple times.
+    #[structopt(long, number_of_values = 1)]
+    include: Vec<String>,
+
+    /// Skip files and directories matching this glob. Can be given multiple times.
+    #[structopt(long, number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// Don't respect .gitignore, .ignore and global git exclude files
+    #[structopt(long)]
+    no_ignore: bool,
+}
+
+impl WalkArgs {
+    fn into_options(self, default_max_file_size: Option<u64>) -> Result<WalkOptions> {
+        let max_file_size = match self.max_file_size {
+            Some(0) => None,
+            Some(max) => Some(max),
+            None => default_max_file_size,
+        };
+        Ok(WalkOptions {
+            max_file_size,
+            include: glob_set(&self.include)?,
+            exclude: glob_set(&self.exclude)?,
+            no_ignore: self.no_ignore,
+        })
+    }
+}
 
 #[derive(StructOpt, Debug)]
 #[structopt(about = "an AST based Nix documentation tool")]
 enum Args {
-    /// Search a directory of nix files for the given function
+    /// Search directories of nix files for the given function
     Search {
-        /// Regex to search with
-        re: String,
+        /// Regex to search with. With --type or --takes, it narrows their results down by name
+        /// and can be left out, or given as '' to search other directories without narrowing.
+        re: Option<String>,
 
-        /// Directory to search
-        #[structopt(default_value = ".")]
-        dir: PathBuf,
+        /// Directories to search, by default the current one. `<name>` is looked up in the nix
+        /// search path like in a nix expression, e.g. `<nixpkgs>` or `<nixpkgs/lib>`.
+        dirs: Vec<String>,
+
+        /// Find functions by their declared type instead, e.g. `string -> int -> string`. Type
+        /// variables and the order of arguments don't have to match exactly.
+        #[structopt(long = "type")]
+        type_query: Option<String>,
+
+        /// Find functions with a `{ ... }` argument that has these formals, e.g.
+        /// `--takes stdenv,fetchFromGitHub`. Files that are a function, like packages, are
+        /// included, and so are undocumented functions.
+        #[structopt(long, use_delimiter = true, number_of_values = 1)]
+        takes: Vec<String>,
+
+        /// Add an entry to the front of the nix search path used to look up `<name>` directories,
+        /// as `name=path` or `path`, like `nix -I`. The rest comes from NIX_PATH.
+        #[structopt(short = "I", long = "search-path", number_of_values = 1)]
+        search_path: Vec<String>,
+
+        /// Output format: text, json (one array of hits) or jsonl (one hit per line)
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+
+        /// Also show functions without documentation comments
+        #[structopt(long)]
+        include_undocumented: bool,
+
+        /// What to match the query against: name (function names and attribute paths), doc
+        /// (documentation comments) or both
+        #[structopt(long = "in", default_value = "name")]
+        target: SearchTarget,
+
+        /// Treat the query as whitespace separated keywords that all have to appear, ignoring
+        /// case, instead of as a regex
+        #[structopt(long)]
+        keywords: bool,
+
+        /// How to order results: relevance (best matches and shallowest files first) or path
+        #[structopt(long, default_value = "relevance")]
+        sort: SortOrder,
+
+        /// When to use colours: auto (if stdout is a terminal and NO_COLOR is unset), always or
+        /// never
+        #[structopt(long, default_value = "auto")]
+        color: ColorChoice,
+
+        /// Only show the type or signature of each function and the first paragraph of its docs
+        #[structopt(long)]
+        summary: bool,
+
+        /// Show the definition of each function below its docs, syntax highlighted
+        #[structopt(long)]
+        source: bool,
+
+        /// Show at most this many lines of the definition of each function. Implies --source.
+        #[structopt(long, value_name = "N")]
+        source_lines: Option<usize>,
+
+        /// Print the syntax errors of the searched files to stderr. Functions in the well-formed
+        /// parts of those files are found either way.
+        #[structopt(long)]
+        show_parse_errors: bool,
+
+        #[structopt(flatten)]
+        walk: WalkArgs,
     },
 
     /// Generates a ctags compatible database for a directory of nix files
@@ -36,22 +178,167 @@ enum Args {
         /// Pass -1 to disable this optimization.
         #[structopt(long, default_value = "500")]
         max_cardinality: i32,
+
+        #[structopt(flatten)]
+        walk: WalkArgs,
     },
 }
 
-fn main() -> Result<()> {
-    let args = Args::from_args();
+/// Writes out search results in the given format as they arrive
+fn print_results(
+    results: impl Iterator<Item = SearchResult>,
+    format: OutputFormat,
+    options: &FormatOptions,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let line = options.paint(&"─".repeat(45), |s| s.color(Color::Grey27));
+    let mut is_first = true;
+
+    if format == OutputFormat::Json {
+        write!(out, "[")?;
+    }
+
+    for result in results {
+        match format {
+            OutputFormat::Text => {
+                // summaries are short enough to not need separating
+                if !is_first && !options.summary {
+                    writeln!(out, "{}", &line)?;
+                }
+                writeln!(out, "{}", result.format(options))?;
+            }
+            OutputFormat::Json => {
+                if !is_first {
+                    write!(out, ",")?;
+                }
+                write!(out, "\n{}", serde_json::to_string(&result)?)?;
+            }
+            OutputFormat::JsonLines => writeln!(out, "{}", serde_json::to_string(&result)?)?,
+        }
+        is_first = false;
+    }
+
+    if format == OutputFormat::Json {
+        writeln!(out, "\n]")?;
+    }
+    Ok(())
+}
 
+/// Turns a directory given on the command line into a search root, looking up `<name>` in the
+/// search path
+fn resolve_root(search_path: &SearchPath, dir: String) -> Result<SearchRoot> {
+    match nixpath::lookup_name(&dir) {
+        Some(name) => match search_path.resolve(name) {
+            Some(path) => Ok(SearchRoot { name: dir, path }),
+            None => Err(format!(
+                "{} was not found in the nix search path (add it with -I or NIX_PATH)",
+                dir
+            )
+            .into()),
+        },
+        None if !Path::new(&dir).exists() => Err(format!("{} does not exist", dir).into()),
+        None => Ok(SearchRoot::new(PathBuf::from(dir))),
+    }
+}
+
+fn main() {
+    if let Err(e) = run(Args::from_args()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<()> {
     match args {
-        Args::Search { re, dir } => {
-            let re_match = Regex::new(&re)?;
-            search(&dir, re_match, is_searchable);
+        Args::Search {
+            re,
+            mut dirs,
+            type_query,
+            takes,
+            search_path,
+            format,
+            include_undocumented,
+            target,
+            keywords,
+            sort,
+            color,
+            summary,
+            source,
+            source_lines,
+            show_parse_errors,
+            walk,
+        } => {
+            let mut options = match re {
+                Some(re) if keywords => SearchOptions::keywords(&re),
+                Some(re) => SearchOptions::new(Regex::new(&re)?),
+                None if type_query.is_none() && takes.is_empty() => {
+                    return Err(
+                        "a regex to search with is required unless --type or --takes is given"
+                            .into(),
+                    )
+                }
+                None => SearchOptions::default(),
+            };
+            if !takes.is_empty() {
+                // like SearchOptions::by_formals, since such functions are rarely documented
+                options.formals = takes;
+                options.include_undocumented = true;
+            }
+            if let Some(ty) = type_query {
+                options.type_query = Some(Type::parse(&ty).map_err(Error::from)?);
+            }
+            if dirs.is_empty() {
+                dirs.push(".".to_string());
+            }
+            options.include_undocumented |= include_undocumented;
+            options.target = target;
+            options.source = source || source_lines.is_some();
+            let walk = walk.into_options(Some(MAX_FILE_SIZE))?;
+
+            let search_path = SearchPath::from_env(&search_path);
+            let roots = dirs
+                .into_iter()
+                .map(|dir| resolve_root(&search_path, dir))
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut found = search(&roots, &options, &walk);
+            let mut results = found.by_ref().collect::<Vec<_>>();
+            if show_parse_errors {
+                let mut parse_errors = found.parse_errors().to_vec();
+                parse_errors.sort_by_key(|e| (e.file.clone(), e.line, e.column));
+                for err in parse_errors {
+                    eprintln!("{}", err);
+                }
+            }
+            let mut errors = found.errors().iter().collect::<Vec<_>>();
+            errors.sort_by(|a, b| a.file.cmp(&b.file));
+            for err in errors {
+                eprintln!("Failure handling {}", err);
+            }
+            sort_results(&mut results, sort);
+
+            let stdout = io::stdout();
+            let options = FormatOptions {
+                show_root: roots.len() > 1,
+                summary,
+                source_lines,
+                ..FormatOptions::with_color(color)
+            };
+            let res = print_results(results.into_iter(), format, &options, stdout.lock());
+            match res {
+                // stdout went away (e.g. the pager was closed); nobody is listening anymore
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+                r => r?,
+            }
         }
 
         Args::Tags {
             dir,
             max_cardinality,
+            walk,
         } => {
+            let walk = walk.into_options(None)?;
+
             let h = fs::OpenOptions::new()
                 .write(true)
                 .truncate(true)
@@ -66,10 +353,11 @@ fn main() -> Result<()> {
                 } else {
                     None
                 },
+                &walk,
                 &mut h,
             );
             if let Err(e) = res {
-                eprintln!("Failed while ctags'ing: {:?}", e);
+                eprintln!("Failed while ctags'ing: {}", e);
             }
         }
     }
@@ -0,0 +1,482 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Rendering Markdown documentation for display in a terminal
+use std::ops::Range;
+
+use colorful::core::color_string::CString;
+use colorful::Colorful;
+use pulldown_cmark::{Event, Parser, Tag};
+
+use crate::FormatOptions;
+
+/// Inline styles in effect for some text
+#[derive(Clone, Copy, Debug, Default)]
+struct Style {
+    strong: bool,
+    emphasis: bool,
+    code: bool,
+    heading: bool,
+    /// Matched by the search
+    highlight: bool,
+}
+
+struct Renderer<'a> {
+    options: &'a FormatOptions,
+    width: Option<usize>,
+    style: Style,
+    out: String,
+
+    /// Prefix of every line: indents of list items and quote bars
+    prefix: String,
+    /// Lengths of `prefix` to go back to when the innermost list item or quote ends
+    prefix_stack: Vec<usize>,
+    /// Used instead of `prefix` for the next line, to put a bullet on the first line of an item
+    first_prefix: Option<String>,
+
+    line: String,
+    line_width: usize,
+    line_started: bool,
+    line_has_text: bool,
+
+    word: String,
+    word_width: usize,
+    space_pending: bool,
+
+    /// A blank line is due before the next block
+    need_blank: bool,
+    /// Numbers of the next items of the enclosing lists, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    /// Text of the code block being read
+    code_block: Option<String>,
+    /// Destination and text so far of the link being read
+    link: Option<(String, String)>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(options: &'a FormatOptions, width: Option<usize>) -> Self {
+        Renderer {
+            options,
+            width,
+            style: Style::default(),
+            out: String::new(),
+            prefix: String::new(),
+            prefix_stack: Vec::new(),
+            first_prefix: None,
+            line: String::new(),
+            line_width: 0,
+            line_started: false,
+            line_has_text: false,
+            word: String::new(),
+            word_width: 0,
+            space_pending: false,
+            need_blank: false,
+            lists: Vec::new(),
+            code_block: None,
+            link: None,
+        }
+    }
+
+    fn paint(&self, s: &str, style: Style) -> String {
+        self.options.paint(s, |s| {
+            let mut c = CString::new(s);
+            if style.highlight {
+                c = c.yellow().bold();
+            } else if style.code {
+                c = c.cyan();
+            }
+            if style.strong || style.heading {
+                c = c.bold();
+            }
+            if style.emphasis || style.heading {
+                c = c.underlined();
+            }
+            c
+        })
+    }
+
+    fn start_line(&mut self) {
+        if !self.line_started {
+            self.line = self
+                .first_prefix
+                .take()
+                .unwrap_or_else(|| self.prefix.clone());
+            self.line_width = self.line.chars().count();
+            self.line_started = true;
+        }
+    }
+
+    fn finish_line(&mut self) {
+        if self.line_started {
+            self.out.push_str(self.line.trim_end());
+            self.out.push('\n');
+        }
+        self.line_started = false;
+        self.line_has_text = false;
+    }
+
+    /// Puts the word being built onto the line, wrapping first if it doesn't fit
+    fn flush_word(&mut self) {
+        if self.word.is_empty() {
+            return;
+        }
+        if self.line_has_text && self.space_pending {
+            let fits = match self.width {
+                Some(width) => self.line_width + 1 + self.word_width <= width,
+                None => true,
+            };
+            if fits {
+                self.line.push(' ');
+                self.line_width += 1;
+            } else {
+                self.finish_line();
+            }
+        }
+        self.start_line();
+        self.line.push_str(&self.word);
+        self.line_width += self.word_width;
+        self.line_has_text = true;
+        self.word.clear();
+        self.word_width = 0;
+        self.space_pending = false;
+    }
+
+    /// Adds inline text, breaking it into words at whitespace
+    fn push_text(&mut self, text: &str, style: Style) {
+        if let Some((_, link_text)) = &mut self.link {
+            link_text.push_str(text);
+        }
+        let mut run = String::new();
+        for ch in text.chars() {
+            if ch.is_whitespace() {
+                self.push_run(&mut run, style);
+                self.flush_word();
+                self.space_pending = true;
+            } else {
+                run.push(ch);
+            }
+        }
+        self.push_run(&mut run, style);
+    }
+
+    fn push_run(&mut self, run: &mut String, style: Style) {
+        if !run.is_empty() {
+            self.word.push_str(&self.paint(run, style));
+            self.word_width += run.chars().count();
+            run.clear();
+        }
+    }
+
+    fn start_block(&mut self) {
+        self.flush_word();
+        self.finish_line();
+        if self.need_blank && !self.out.is_empty() {
+            self.out.push_str(self.prefix.trim_end());
+            self.out.push('\n');
+        }
+        self.need_blank = false;
+        self.space_pending = false;
+    }
+
+    fn end_block(&mut self) {
+        self.flush_word();
+        self.finish_line();
+        self.need_blank = true;
+    }
+
+    fn push_prefix(&mut self, s: &str) {
+        self.prefix_stack.push(self.prefix.len());
+        self.prefix.push_str(s);
+    }
+
+    fn pop_prefix(&mut self) {
+        if let Some(len) = self.prefix_stack.pop() {
+            self.prefix.truncate(len);
+        }
+    }
+
+    fn code_block(&mut self, code: &str) {
+        for line in code.trim_end_matches('\n').split('\n') {
+            let line = self.paint(
+                line,
+                Style {
+                    code: true,
+                    ..self.style
+                },
+            );
+            self.out.push_str(&self.prefix);
+            self.out.push_str("  ");
+            self.out.push_str(line.trim_end());
+            self.out.push('\n');
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading(level, _, _) => {
+                self.start_block();
+                self.style.heading = true;
+                if !self.options.color {
+                    // without colours, the hashes are the only thing showing it is a heading
+                    let hashes = "#".repeat(level as usize);
+                    self.push_text(&hashes, Style::default());
+                    self.flush_word();
+                    self.space_pending = true;
+                }
+            }
+            Tag::BlockQuote => {
+                self.start_block();
+                self.push_prefix(if self.options.color { "│ " } else { "> " });
+            }
+            Tag::CodeBlock(_) => {
+                self.start_block();
+                self.code_block = Some(String::new());
+            }
+            Tag::List(start) => {
+                self.start_block();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush_word();
+                self.finish_line();
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.first_prefix = Some(format!("{}{}", self.prefix, bullet));
+                self.push_prefix(&" ".repeat(bullet.len()));
+                self.need_blank = false;
+            }
+            Tag::Emphasis => self.style.emphasis = true,
+            Tag::Strong => self.style.strong = true,
+            Tag::Link(_, dest, _) | Tag::Image(_, dest, _) => {
+                self.link = Some((dest.to_string(), String::new()));
+            }
+            _ => (),
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.end_block(),
+            Tag::Heading(..) => {
+                self.style.heading = false;
+                self.end_block();
+            }
+            Tag::BlockQuote => {
+                self.end_block();
+                self.pop_prefix();
+            }
+            Tag::CodeBlock(_) => {
+                if let Some(code) = self.code_block.take() {
+                    self.code_block(&code);
+                }
+                self.end_block();
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+                self.end_block();
+            }
+            Tag::Item => {
+                self.flush_word();
+                self.finish_line();
+                self.pop_prefix();
+            }
+            Tag::Emphasis => self.style.emphasis = false,
+            Tag::Strong => self.style.strong = false,
+            Tag::Link(..) | Tag::Image(..) => {
+                if let Some((dest, text)) = self.link.take() {
+                    // autolinks already show where they go
+                    if !dest.is_empty() && dest != text {
+                        self.push_text(&format!(" <{}>", dest), self.style);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Adds text that is at `range` in the source, highlighting the parts in `highlights`
+    fn text(&mut self, text: &str, range: Range<usize>, highlights: &[Range<usize>]) {
+        let mut pieces = Vec::new();
+        // escapes and entities make the text differ from the source, in which case the offsets
+        // don't line up and we can't highlight
+        if range.len() == text.len() {
+            let mut last = 0;
+            for hl in highlights {
+                let start = hl.start.max(range.start).saturating_sub(range.start);
+                let end = hl.end.min(range.end).saturating_sub(range.start);
+                if start < end && start >= last {
+                    pieces.push((&text[last..start], false));
+                    pieces.push((&text[start..end], true));
+                    last = end;
+                }
+            }
+            pieces.push((&text[last..], false));
+        } else {
+            pieces.push((text, false));
+        }
+
+        for (piece, highlight) in pieces {
+            let style = Style {
+                highlight,
+                ..self.style
+            };
+            match &mut self.code_block {
+                Some(code) if !highlight => code.push_str(piece),
+                Some(_) => {
+                    let painted = self.paint(piece, style);
+                    if let Some(code) = &mut self.code_block {
+                        code.push_str(&painted);
+                    }
+                }
+                None => self.push_text(piece, style),
+            }
+        }
+    }
+}
+
+/// Blanks out the `:::` lines of fenced divs, which nixpkgs uses around examples, keeping the
+/// offsets of everything else the same
+fn blank_fenced_divs(doc: &str) -> String {
+    doc.split('\n')
+        .map(|line| {
+            if line.trim_start().starts_with(":::") {
+                " ".repeat(line.len())
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders the Markdown `doc` for a terminal, wrapping paragraphs to `width` columns if given.
+/// `highlights` are byte ranges of `doc` to highlight, like search matches.
+pub fn render(
+    doc: &str,
+    highlights: &[Range<usize>],
+    width: Option<usize>,
+    options: &FormatOptions,
+) -> String {
+    let source = blank_fenced_divs(doc);
+    let mut renderer = Renderer::new(options, width);
+
+    for (event, range) in Parser::new(&source).into_offset_iter() {
+        match event {
+            Event::Start(tag) => renderer.start(tag),
+            Event::End(tag) => renderer.end(tag),
+            Event::Text(text) => renderer.text(&text, range, highlights),
+            Event::Code(code) => {
+                let style = Style {
+                    code: true,
+                    ..renderer.style
+                };
+                if options.color {
+                    renderer.push_text(&code, style);
+                } else {
+                    renderer.push_text(&format!("`{}`", code), style);
+                }
+            }
+            Event::Html(html) => renderer.push_text(&html, renderer.style),
+            Event::FootnoteReference(name) => {
+                renderer.push_text(&format!("[^{}]", name), renderer.style)
+            }
+            Event::SoftBreak => {
+                renderer.flush_word();
+                renderer.space_pending = true;
+            }
+            Event::HardBreak => {
+                renderer.flush_word();
+                renderer.finish_line();
+            }
+            Event::Rule => {
+                renderer.start_block();
+                let rule = "─".repeat(width.unwrap_or(20).min(20));
+                renderer.push_text(&rule, Style::default());
+                renderer.end_block();
+            }
+            Event::TaskListMarker(done) => {
+                renderer.push_text(if done { "[x]" } else { "[ ]" }, renderer.style)
+            }
+        }
+    }
+    renderer.flush_word();
+    renderer.finish_line();
+
+    let out = renderer.out;
+    out.trim_end_matches('\n').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(doc: &str, width: Option<usize>) -> String {
+        render(doc, &[], width, &FormatOptions::plain())
+    }
+
+    #[test]
+    fn blocks() {
+        let doc = r#"# Heading
+
+Some *emphasised* and **strong** text with `inline code`.
+
+* one
+* two
+  1. nested
+  2. list
+
+> quoted
+
+```nix
+f = x: x;
+```
+
+:::{.example}
+See [the manual](https://nixos.org/manual).
+:::"#;
+        assert_eq!(
+            plain(doc, None),
+            r#"# Heading
+
+Some emphasised and strong text with `inline code`.
+
+- one
+- two
+  1. nested
+  2. list
+
+> quoted
+
+  f = x: x;
+
+See the manual <https://nixos.org/manual>."#
+        );
+    }
+
+    #[test]
+    fn wrapping() {
+        let doc = "A paragraph that is long enough to wrap.\n\n* an item that wraps as well";
+        assert_eq!(
+            plain(doc, Some(16)),
+            "A paragraph that\nis long enough\nto wrap.\n\n- an item that\n  wraps as well"
+        );
+    }
+
+    #[test]
+    fn highlights() {
+        let options = FormatOptions {
+            color: true,
+            ..FormatOptions::plain()
+        };
+        let out = render("a *bc* d", &[Range { start: 4, end: 5 }], None, &options);
+        assert!(out.starts_with("a "));
+        assert!(out.contains(&"c".yellow().bold().underlined().to_string()));
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Resolving `<name>` lookup paths against `NIX_PATH` and `-I` entries, like nix does, for entries
+//! that are on the local filesystem
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// One entry of the nix search path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchPathEntry {
+    /// Prefix that lookups have to start with, e.g. `nixpkgs` in `nixpkgs=/some/path`. Entries
+    /// without one apply to any lookup.
+    pub prefix: Option<String>,
+
+    /// Where the entry points
+    pub path: String,
+}
+
+impl SearchPathEntry {
+    /// Parses `name=path` or `path`
+    pub fn parse(s: &str) -> Self {
+        match s.split_once('=') {
+            Some((prefix, path)) => SearchPathEntry {
+                prefix: Some(prefix.to_string()),
+                path: path.to_string(),
+            },
+            None => SearchPathEntry {
+                prefix: None,
+                path: s.to_string(),
+            },
+        }
+    }
+
+    /// Is this something other than a local path, like a tarball URL or a flake reference? Those
+    /// would need nix itself to fetch, so we don't look at them.
+    fn is_remote(&self) -> bool {
+        self.path.contains("://") || self.path.starts_with("flake:")
+    }
+
+    /// Where `lookup` would be found through this entry, if this entry applies to it
+    fn candidate(&self, lookup: &str) -> Option<PathBuf> {
+        if self.is_remote() {
+            return None;
+        }
+        let rest = match &self.prefix {
+            None => lookup,
+            Some(prefix) if lookup == prefix => "",
+            Some(prefix) => lookup.strip_prefix(prefix)?.strip_prefix('/')?,
+        };
+        let path = Path::new(&self.path);
+        Some(if rest.is_empty() {
+            path.to_owned()
+        } else {
+            path.join(rest)
+        })
+    }
+}
+
+/// The nix search path, used to resolve `<name>` lookups
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchPath {
+    /// Entries in order of priority
+    pub entries: Vec<SearchPathEntry>,
+}
+
+impl SearchPath {
+    /// Parses a colon separated search path in the format of `NIX_PATH`
+    pub fn parse(s: &str) -> Self {
+        let mut parts: Vec<String> = Vec::new();
+        for part in s.split(':') {
+            match parts.last_mut() {
+                // the colon was part of a URL scheme like https://, put it back
+                Some(last) if part.starts_with("//") => {
+                    last.push(':');
+                    last.push_str(part);
+                }
+                _ => parts.push(part.to_string()),
+            }
+        }
+        SearchPath {
+            entries: parts
+                .iter()
+                .filter(|p| !p.is_empty())
+                .map(|p| SearchPathEntry::parse(p))
+                .collect(),
+        }
+    }
+
+    /// Builds the search path from `-I` style `includes`, which take priority, followed by the
+    /// `NIX_PATH` environment variable
+    pub fn from_env(includes: &[String]) -> Self {
+        let mut entries = includes
+            .iter()
+            .map(|i| SearchPathEntry::parse(i))
+            .collect::<Vec<_>>();
+        if let Ok(nix_path) = env::var("NIX_PATH") {
+            entries.extend(Self::parse(&nix_path).entries);
+        }
+        SearchPath { entries }
+    }
+
+    /// Finds the first existing path for `lookup`, which is the `nixpkgs/lib` in `<nixpkgs/lib>`
+    pub fn resolve(&self, lookup: &str) -> Option<PathBuf> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.candidate(lookup))
+            .find(|path| path.exists())
+    }
+}
+
+/// If `s` is a `<name>` lookup path, returns the `name`
+pub fn lookup_name(s: &str) -> Option<&str> {
+    s.strip_prefix('<')?.strip_suffix('>')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let path = SearchPath::parse("nixpkgs=/a/b:/c:foo=https://example.com/x.tar.gz:");
+        assert_eq!(
+            path.entries,
+            [
+                SearchPathEntry {
+                    prefix: Some("nixpkgs".to_string()),
+                    path: "/a/b".to_string(),
+                },
+                SearchPathEntry {
+                    prefix: None,
+                    path: "/c".to_string(),
+                },
+                SearchPathEntry {
+                    prefix: Some("foo".to_string()),
+                    path: "https://example.com/x.tar.gz".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let path = SearchPath {
+            entries: vec![
+                SearchPathEntry::parse("remote=https://example.com/x.tar.gz"),
+                SearchPathEntry::parse("nixpkgs=does-not-exist"),
+                SearchPathEntry::parse("td=testdata"),
+                SearchPathEntry::parse("."),
+            ],
+        };
+        assert_eq!(path.resolve("td"), Some(PathBuf::from("testdata")));
+        assert_eq!(
+            path.resolve("td/test.nix"),
+            Some(PathBuf::from("testdata/test.nix"))
+        );
+        assert_eq!(path.resolve("testdata"), Some(PathBuf::from("./testdata")));
+        assert_eq!(path.resolve("tdx"), None);
+        assert_eq!(path.resolve("nixpkgs"), None);
+        assert_eq!(path.resolve("remote"), None);
+    }
+
+    #[test]
+    fn lookup() {
+        assert_eq!(lookup_name("<nixpkgs/lib>"), Some("nixpkgs/lib"));
+        assert_eq!(lookup_name("./nixpkgs"), None);
+    }
+}
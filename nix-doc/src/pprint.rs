@@ -2,39 +2,468 @@
 //
 // SPDX-License-Identifier: BSD-2-Clause OR MIT
 
-use rnix::types::{Lambda, TypedNode};
-use rnix::SyntaxKind::*;
+use std::fmt;
 
-/// Pretty-prints the arguments to a function
-pub fn pprint_args(lambda: &Lambda) -> String {
-    // TODO: handle docs directly on NODE_IDENT args (uncommon case)
-    let mut lambda = lambda.clone();
-    let mut out = String::new();
-    loop {
-        let arg = lambda.arg().unwrap();
-        match arg.kind() {
-            NODE_IDENT => {
-                out.push_str(&arg.to_string());
-                out.push_str(": ");
-                let body = lambda.body().unwrap();
-                if body.kind() == NODE_LAMBDA {
-                    lambda = Lambda::cast(body).unwrap();
-                } else {
-                    break;
-                }
+use rnix::types::{Ident, Lambda, Pattern, TokenWrapper, TypedNode};
+use rnix::SyntaxKind::{self, *};
+use rnix::{NodeOrToken, SyntaxNode, SyntaxToken};
+use serde::Serialize;
+
+use crate::cleanup_comments;
+
+/// Default values longer than this many characters are cut off
+const MAX_DEFAULT_LEN: usize = 30;
+
+/// One formal of a `{ ... }` pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Formal {
+    pub name: String,
+
+    /// Source of the default value on one line, cut off if it is long
+    pub default: Option<String>,
+
+    /// Documentation from comments next to the formal
+    pub doc: Option<String>,
+}
+
+/// Documentation of a formal, for the arguments table of a search result
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FormalDoc {
+    pub name: String,
+    pub doc: String,
+}
+
+/// One argument of a curried function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Param {
+    /// Source text of arguments we couldn't make sense of, e.g. from a file with syntax errors
+    Raw(String),
+    /// `x: ...`
+    Ident(String),
+    /// `{ a, b ? 1, ... }: ...`
+    Pattern {
+        formals: Vec<Formal>,
+        ellipsis: bool,
+        /// Name bound to the whole argument with `@`
+        bind: Option<String>,
+        /// Whether the binding is written `args@{ ... }` rather than `{ ... }@args`
+        bind_before: bool,
+    },
+}
+
+/// The arguments of a function, for pretty-printing
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Param>,
+}
+
+/// Why the arguments of a function couldn't be read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The function has no argument, which happens in files with syntax errors
+    MissingArg,
+    /// There is a syntax error in the arguments
+    SyntaxError,
+    /// The argument is neither a name nor a `{ ... }` pattern
+    UnexpectedArg(SyntaxKind),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::MissingArg => write!(f, "function has no argument"),
+            SignatureError::SyntaxError => write!(f, "syntax error in function arguments"),
+            SignatureError::UnexpectedArg(kind) => write!(f, "unexpected argument {:?}", kind),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// The source of the arguments of a curried function: everything up to the body of the innermost
+/// lambda, on one line and without the last `:`
+fn raw_args(lambda: &Lambda) -> String {
+    let start = lambda.node().text_range().start();
+    let mut innermost = lambda.clone();
+    while let Some(inner) = innermost.body().and_then(Lambda::cast) {
+        innermost = inner;
+    }
+    let end = match innermost.body() {
+        Some(body) => body.text_range().start(),
+        None => innermost.node().text_range().end(),
+    };
+    let text = lambda.node().text().to_string();
+    text[..end.to_usize() - start.to_usize()]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(':')
+        .trim_end()
+        .to_string()
+}
+
+/// Puts the source of `node` on one line, cutting it off if it is long
+fn short_source(node: &SyntaxNode) -> String {
+    let text = node
+        .text()
+        .to_string()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.chars().count() > MAX_DEFAULT_LEN {
+        let mut short = text.chars().take(MAX_DEFAULT_LEN - 1).collect::<String>();
+        short.push('…');
+        short
+    } else {
+        text
+    }
+}
+
+/// Finds the comments documenting each entry of `pattern`, in order of the entries.
+///
+/// A comment belongs to the entry after it, unless it is at the end of the line of the entry
+/// before it, like `enableFoo ? false, # whether to build foo`.
+fn formal_comments(pattern: &Pattern) -> Vec<Vec<String>> {
+    let children = pattern.node().children_with_tokens().collect::<Vec<_>>();
+    let entries = children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| child.kind() == NODE_PAT_ENTRY)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let has_newline = |range: &[NodeOrToken<SyntaxNode, SyntaxToken>]| {
+        range.iter().any(|child| match child {
+            NodeOrToken::Token(t) => t.kind() == TOKEN_WHITESPACE && t.text().contains('\n'),
+            NodeOrToken::Node(_) => false,
+        })
+    };
+
+    let mut comments = vec![Vec::new(); entries.len()];
+    for (i, child) in children.iter().enumerate() {
+        let text = match child {
+            NodeOrToken::Token(t) if t.kind() == TOKEN_COMMENT => t.text().to_string(),
+            _ => continue,
+        };
+        // ordinals of the entries around the comment
+        let next = entries.iter().position(|&e| e > i);
+        let prev = next.unwrap_or(entries.len()).checked_sub(1);
+
+        let newline_before = has_newline(&children[prev.map_or(0, |p| entries[p])..i]);
+        let newline_after = has_newline(&children[i..next.map_or(children.len(), |n| entries[n])]);
+        let owner = match (prev, next) {
+            (Some(prev), next) if !newline_before && (newline_after || next.is_none()) => prev,
+            (_, Some(next)) => next,
+            (_, None) => continue,
+        };
+        comments[owner].push(text);
+    }
+    comments
+}
+
+fn pattern_param(pattern: &Pattern) -> Param {
+    let formals = pattern
+        .entries()
+        .zip(formal_comments(pattern))
+        .filter_map(|(entry, comments)| {
+            let doc = cleanup_comments(&mut comments.iter().rev());
+            Some(Formal {
+                name: entry.name()?.as_str().to_string(),
+                default: entry.default().as_ref().map(short_source),
+                doc: Some(doc).filter(|doc| !doc.is_empty()),
+            })
+        })
+        .collect();
+    let bind = pattern.at();
+    let open_brace = pattern
+        .node()
+        .children_with_tokens()
+        .find(|child| child.kind() == TOKEN_CURLY_B_OPEN);
+    let bind_before = match (&bind, open_brace) {
+        (Some(bind), Some(brace)) => bind.node().text_range().start() < brace.text_range().start(),
+        _ => false,
+    };
+    Param::Pattern {
+        formals,
+        ellipsis: pattern.ellipsis(),
+        bind: bind.map(|bind| bind.as_str().to_string()),
+        bind_before,
+    }
+}
+
+impl Signature {
+    /// Documentation of the formals that have any
+    pub fn formal_docs(&self) -> Vec<FormalDoc> {
+        self.params
+            .iter()
+            .flat_map(|param| match param {
+                Param::Pattern { formals, .. } => formals.as_slice(),
+                _ => &[],
+            })
+            .filter_map(|formal| {
+                Some(FormalDoc {
+                    name: formal.name.clone(),
+                    doc: formal.doc.clone()?,
+                })
+            })
+            .collect()
+    }
+
+    /// What the first pattern argument accepts, as in `a: { b, c ? 1, ... }: ...`
+    pub fn pattern_info(&self) -> Option<PatternInfo> {
+        self.params.iter().find_map(|param| match param {
+            Param::Pattern {
+                formals,
+                ellipsis,
+                bind,
+                ..
+            } => Some(PatternInfo {
+                formals: formals.iter().map(|formal| formal.name.clone()).collect(),
+                defaults: formals
+                    .iter()
+                    .filter(|formal| formal.default.is_some())
+                    .map(|formal| formal.name.clone())
+                    .collect(),
+                ellipsis: *ellipsis,
+                bind: bind.clone(),
+            }),
+            _ => None,
+        })
+    }
+
+    /// Reads the arguments of a curried function
+    pub fn parse(lambda: &Lambda) -> Result<Signature, SignatureError> {
+        let mut lambda = lambda.clone();
+        let mut params = Vec::new();
+        loop {
+            let arg = lambda.arg().ok_or(SignatureError::MissingArg)?;
+            if arg.kind() == NODE_ERROR || arg.descendants().any(|n| n.kind() == NODE_ERROR) {
+                return Err(SignatureError::SyntaxError);
             }
-            NODE_PATTERN => {
-                out.push_str(&arg.to_string());
-                out.push_str(": ");
+            if let Some(pattern) = Pattern::cast(arg.clone()) {
+                params.push(pattern_param(&pattern));
                 break;
             }
-            t => {
-                unreachable!("unhandled arg type {:?}", t);
+            let kind = arg.kind();
+            match Ident::cast(arg) {
+                Some(ident) => params.push(Param::Ident(ident.as_str().to_string())),
+                None => return Err(SignatureError::UnexpectedArg(kind)),
+            }
+            match lambda.body().and_then(Lambda::cast) {
+                Some(body) => lambda = body,
+                None => break,
             }
         }
+        Ok(Signature { params })
+    }
+
+    /// Reads the arguments of a curried function, falling back to their source text if they
+    /// can't be understood
+    pub fn of(lambda: &Lambda) -> Signature {
+        Self::parse(lambda).unwrap_or_else(|_| Signature {
+            params: vec![Param::Raw(raw_args(lambda))],
+        })
+    }
+
+    /// Renders the signature, spreading patterns over several lines if it would be wider than
+    /// `width`
+    pub fn render(&self, width: Option<usize>) -> String {
+        let one_line = self.render_with(false);
+        match width {
+            Some(width) if one_line.chars().count() > width => self.render_with(true),
+            _ => one_line,
+        }
+    }
+
+    fn render_with(&self, multiline: bool) -> String {
+        let mut out = String::new();
+        for param in &self.params {
+            match param {
+                Param::Ident(name) | Param::Raw(name) => out.push_str(name),
+                Param::Pattern {
+                    formals,
+                    ellipsis,
+                    bind,
+                    bind_before,
+                } => {
+                    let mut items = formals
+                        .iter()
+                        .map(|formal| match &formal.default {
+                            Some(default) => format!("{} ? {}", formal.name, default),
+                            None => formal.name.clone(),
+                        })
+                        .collect::<Vec<_>>();
+                    if *ellipsis {
+                        items.push("...".to_string());
+                    }
+
+                    if let (Some(bind), true) = (bind, bind_before) {
+                        out.push_str(bind);
+                        out.push('@');
+                    }
+                    if items.is_empty() {
+                        out.push_str("{ }");
+                    } else if multiline {
+                        out.push_str("{\n");
+                        for item in items {
+                            // `...` has to come last, so it doesn't get a trailing comma
+                            let comma = if item == "..." { "" } else { "," };
+                            out.push_str(&format!("  {}{}\n", item, comma));
+                        }
+                        out.push('}');
+                    } else {
+                        out.push_str(&format!("{{ {} }}", items.join(", ")));
+                    }
+                    if let (Some(bind), false) = (bind, bind_before) {
+                        out.push('@');
+                        out.push_str(bind);
+                    }
+                }
+            }
+            out.push_str(": ");
+        }
+        out.push_str("...");
+        out
+    }
+}
+
+/// Pretty-prints the arguments to a function on one line
+pub fn pprint_args(lambda: &Lambda) -> String {
+    Signature::of(lambda).render(None)
+}
+
+/// What the `{ ... }` pattern argument of a function accepts
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PatternInfo {
+    /// Names of the formals, in order
+    pub formals: Vec<String>,
+
+    /// The formals that have a default value
+    pub defaults: Vec<String>,
+
+    /// Does the pattern end in `...`, accepting other attributes too?
+    pub ellipsis: bool,
+
+    /// Name the whole argument is bound to with `@`
+    pub bind: Option<String>,
+}
+
+impl PatternInfo {
+    /// Does the pattern have all of the formals in `names`?
+    pub fn takes_all<S: AsRef<str>>(&self, names: &[S]) -> bool {
+        names
+            .iter()
+            .all(|name| self.formals.iter().any(|formal| formal == name.as_ref()))
     }
-    out.push_str("...");
-    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    //pprint_arg(lambda.arg());
+    fn signature(src: &str) -> Signature {
+        let ast = rnix::parse(src).as_result().unwrap();
+        let lambda = ast.node().descendants().find_map(Lambda::cast).unwrap();
+        Signature::of(&lambda)
+    }
+
+    #[test]
+    fn render() {
+        let sig = signature(
+            "a: args@{ b, # comment\n c ? { x = 1; y = 2; z = 3; w = 4; }, d ? 1, ... }: a",
+        );
+        assert_eq!(
+            sig.render(None),
+            "a: args@{ b, c ? { x = 1; y = 2; z = 3; w = 4;…, d ? 1, ... }: ..."
+        );
+        assert_eq!(
+            sig.render(Some(40)),
+            "a: args@{\n  b,\n  c ? { x = 1; y = 2; z = 3; w = 4;…,\n  d ? 1,\n  ...\n}: ..."
+        );
+        assert_eq!(signature("{ }@x: x").render(None), "{ }@x: ...");
+        assert_eq!(signature("a: b: a").render(Some(4)), "a: b: ...");
+    }
+
+    #[test]
+    fn pattern_info() {
+        assert_eq!(
+            signature("a: args@{ b, c ? 1, ... }: a").pattern_info(),
+            Some(PatternInfo {
+                formals: vec!["b".to_string(), "c".to_string()],
+                defaults: vec!["c".to_string()],
+                ellipsis: true,
+                bind: Some("args".to_string()),
+            })
+        );
+        assert_eq!(signature("a: b: a").pattern_info(), None);
+        // patterns with syntax errors in them aren't trusted
+        let ast = rnix::parse("{ b, c ? }: b");
+        let lambda = ast.node().descendants().find_map(Lambda::cast).unwrap();
+        assert_eq!(Signature::of(&lambda).pattern_info(), None);
+    }
+
+    #[test]
+    fn formal_docs() {
+        let sig = signature(
+            r#"{ lib # the library
+, stdenv
+  # whether to build foo
+  # and bar
+, enableFoo ? false
+, b, /* doc */ c
+, d # last one
+}: 1"#,
+        );
+        let docs = sig
+            .formal_docs()
+            .into_iter()
+            .map(|d| (d.name, d.doc))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("lib", "the library"),
+            ("enableFoo", "whether to build foo\nand bar"),
+            ("c", "doc"),
+            ("d", "last one"),
+        ];
+        assert_eq!(
+            docs,
+            expected.map(|(name, doc)| (name.to_string(), doc.to_string()))
+        );
+
+        let sig = signature("{\n  # the source\n  src,\n  enableFoo ? false, # foo\n  ...\n}: 1");
+        assert_eq!(
+            sig.formal_docs(),
+            [
+                FormalDoc {
+                    name: "src".to_string(),
+                    doc: "the source".to_string(),
+                },
+                FormalDoc {
+                    name: "enableFoo".to_string(),
+                    doc: "foo".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn broken() {
+        let lambda = |src: &str| {
+            let ast = rnix::parse(src);
+            let lambda = ast.node().descendants().find_map(Lambda::cast).unwrap();
+            (
+                Signature::parse(&lambda),
+                Signature::of(&lambda).render(None),
+            )
+        };
+        let (parsed, rendered) = lambda("{ a, b ? }: a");
+        assert_eq!(parsed, Err(SignatureError::SyntaxError));
+        // the error swallowed the body, so it is part of the raw text
+        assert_eq!(rendered, "{ a, b ? }: a: ...");
+
+        let (parsed, rendered) = lambda("x: { a ? , b }:");
+        assert!(parsed.is_err());
+        assert_eq!(rendered, "x: { a ? , b }: ...");
+
+        assert_eq!(lambda("x:").1, "x: ...");
+    }
 }
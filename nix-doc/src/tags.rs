@@ -17,10 +17,11 @@ use rnix::{
     SyntaxKind::*,
     AST,
 };
-use walkdir::WalkDir;
 
+use crate::lineindex::LineIndex;
 use crate::threadpool::ThreadPool;
-use crate::{is_ignored, is_searchable};
+use crate::walk::WalkOptions;
+use crate::Error;
 
 const DEBUG_TIMERS: bool = false;
 
@@ -54,23 +55,6 @@ enum SyntacticKind {
     Inherit = 1,
 }
 
-macro_rules! impl_from {
-    ($on:ty, $variant:ident, $ty:ty) => {
-        impl From<$ty> for $on {
-            fn from(f: $ty) -> $on {
-                <$on>::$variant(f)
-            }
-        }
-    };
-}
-
-#[derive(Debug)]
-pub enum Error {
-    Io(io::Error),
-}
-
-impl_from!(Error, Io, io::Error);
-
 #[derive(PartialEq, Eq)]
 /// One ctags file entry
 struct Tag {
@@ -92,22 +76,20 @@ struct Tag {
 
 impl PartialOrd for Tag {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(
-            self.name
-                .cmp(&other.name)
-                .then_with(|| self.syntactic.cmp(&other.syntactic))
-                .then_with(|| self.kind.cmp(&other.kind))
-                .then_with(|| self.path.cmp(&other.path))
-                // by the time we are comparing addr, we're really in the
-                // weeds.
-                .then_with(|| self.addr.cmp(&other.addr)),
-        )
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Tag {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.name
+            .cmp(&other.name)
+            .then_with(|| self.syntactic.cmp(&other.syntactic))
+            .then_with(|| self.kind.cmp(&other.kind))
+            .then_with(|| self.path.cmp(&other.path))
+            // by the time we are comparing addr, we're really in the
+            // weeds.
+            .then_with(|| self.addr.cmp(&other.addr))
     }
 }
 
@@ -154,21 +136,17 @@ impl fmt::Display for Tag {
 
 struct FileJob<'a> {
     file: SmolStr,
-    source: &'a str,
+    lines: LineIndex<'a>,
     results: &'a mut Vec<Tag>,
 }
 
 impl<'a> FileJob<'a> {
-    fn get_source_line(&self, node: &SyntaxNode) -> &str {
+    fn get_source_line(&self, node: &SyntaxNode) -> &'a str {
         let defined_at_start = node.text_range().start().to_usize();
-        let prior = &self.source[..defined_at_start];
-        let line_start = prior.rfind('\n').unwrap_or(0);
-        let after = &self.source[defined_at_start..];
-        let line_end = after
-            .find('\n')
-            .unwrap_or(self.source.len() - defined_at_start);
-        let source_line = &self.source[line_start..defined_at_start + line_end];
-        source_line.strip_prefix('\n').unwrap_or(source_line)
+        self.lines
+            .line(defined_at_start)
+            .and_then(|line| self.lines.line_text(line))
+            .expect("nodes are inside the file")
     }
 
     fn visit_attrset(&mut self, set: &AttrSet) {
@@ -204,16 +182,14 @@ impl<'a> FileJob<'a> {
 
     fn visit_inherit(&mut self, inh: &Inherit) {
         for id in inh.idents() {
-            (|| {
-                let name = id.as_str();
-                self.results.push(Tag {
-                    name: name.into(),
-                    path: self.file.clone(),
-                    addr: self.get_source_line(id.node()).into(),
-                    kind: Kind::Member,
-                    syntactic: SyntacticKind::Inherit,
-                });
-            })();
+            let name = id.as_str();
+            self.results.push(Tag {
+                name: name.into(),
+                path: self.file.clone(),
+                addr: self.get_source_line(id.node()).into(),
+                kind: Kind::Member,
+                syntactic: SyntacticKind::Inherit,
+            });
         }
     }
 
@@ -221,11 +197,13 @@ impl<'a> FileJob<'a> {
         for evt in ast.node().preorder_with_tokens() {
             match evt {
                 rnix::WalkEvent::Enter(ent) => {
-                    ent.into_node().map(|n| match n.kind() {
-                        NODE_ATTR_SET => self.visit_attrset(&AttrSet::cast(n).unwrap()),
-                        NODE_INHERIT => self.visit_inherit(&Inherit::cast(n).unwrap()),
-                        _ => (),
-                    });
+                    if let Some(n) = ent.into_node() {
+                        match n.kind() {
+                            NODE_ATTR_SET => self.visit_attrset(&AttrSet::cast(n).unwrap()),
+                            NODE_INHERIT => self.visit_inherit(&Inherit::cast(n).unwrap()),
+                            _ => (),
+                        }
+                    }
                 }
                 rnix::WalkEvent::Leave(_) => (),
             }
@@ -243,7 +221,7 @@ impl<'a> FileJob<'a> {
 
         let mut job = FileJob {
             file: p_rel,
-            source: &contents,
+            lines: LineIndex::new(&contents),
             results: &mut results,
         };
 
@@ -300,10 +278,11 @@ fn run_cardinality(max_cardinality: u32, data: &mut Vec<Tag>) {
 }
 
 /// Builds a tags database into the given writer with paths relative to the current directory, with
-/// the nix files in `dir`
+/// the nix files in `dir` selected by `walk`
 pub fn run_on_dir(
     dir: &Path,
     max_cardinality: Option<u32>,
+    walk: &WalkOptions,
     mut writer: impl io::Write,
 ) -> Result<(), Error> {
     let pool = ThreadPool::default();
@@ -313,13 +292,8 @@ pub fn run_on_dir(
 
     //println!("searching {}", dir.display());
     let walk_t = Timer::new();
-    for direntry in WalkDir::new(dir)
-        .into_iter()
-        .filter_entry(|e| !is_ignored(e))
-        .filter_map(|e| e.ok())
-        .filter(|e| is_searchable(e.path()) && e.path().is_file())
-    {
-        let path = curdir.join(direntry.into_path());
+    for path in walk.files(dir) {
+        let path = curdir.join(path);
         let relpath = match pathdiff::diff_paths(&path, &curdir) {
             Some(p) => Arc::new(p),
             None => continue,
@@ -363,7 +337,7 @@ pub fn run_on_dir(
     }
 
     for tag in out {
-        write!(&mut writer, "{}\n", tag)?;
+        writeln!(&mut writer, "{}", tag)?;
     }
     write_t.debug_print("write time");
 
@@ -385,7 +359,13 @@ mod tests {
         println!("cwd: {:?}", &curdir);
         let mut out = Vec::new();
 
-        run_on_dir(&PathBuf::from("."), max_cardinality, &mut out).unwrap();
+        run_on_dir(
+            &PathBuf::from("."),
+            max_cardinality,
+            &WalkOptions::default(),
+            &mut out,
+        )
+        .unwrap();
         let out_s = std::str::from_utf8(&out).unwrap();
         println!("{}", out_s);
 
@@ -0,0 +1,587 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Type signatures as written in `Type:` sections of nixpkgs docs, and matching them against a
+//! query Hoogle style
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+/// A type in the informal notation nixpkgs docs use, e.g. `(a -> b) -> [a] -> [b]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    /// A type variable like `a`
+    Var(String),
+    /// `any`, which is compatible with everything
+    Any,
+    /// A named type, possibly with parameters, e.g. `string` or `list a`
+    Con(String, Vec<Type>),
+    /// A function
+    Fun(Box<Type>, Box<Type>),
+    /// One of several types, `a | b`
+    Union(Vec<Type>),
+}
+
+/// How well a function's declared type matched a type query, best first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeMatch {
+    /// The same type, up to the names of type variables
+    Exact,
+    /// Type variables on either side can be filled in to make the types the same
+    Unifies,
+    /// Unifies once the arguments are put in a different order
+    Reordered,
+}
+
+/// Why a type could not be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeError {
+    /// A `{` without its `}`
+    UnclosedRecord,
+    /// A character that can't appear in a type
+    UnexpectedChar(char),
+    /// A token in the wrong place, along with what was expected there if only one thing fits
+    Unexpected {
+        found: String,
+        expected: Option<String>,
+    },
+    /// The type stopped in the middle, along with what was expected next if only one thing fits
+    UnexpectedEnd { expected: Option<String> },
+    /// More tokens after a complete type
+    Trailing { found: String },
+    /// A parameter given to something other than a named type, like `f a`
+    NotNamed,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::UnclosedRecord => write!(f, "unclosed `{{` in type"),
+            TypeError::UnexpectedChar(c) => write!(f, "unexpected `{}` in type", c),
+            TypeError::Unexpected {
+                found,
+                expected: Some(expected),
+            } => write!(f, "expected `{}` in type, found `{}`", expected, found),
+            TypeError::Unexpected {
+                found,
+                expected: None,
+            } => write!(f, "unexpected `{}` in type", found),
+            TypeError::UnexpectedEnd {
+                expected: Some(expected),
+            } => write!(f, "expected `{}`, found the end of the type", expected),
+            TypeError::UnexpectedEnd { expected: None } => write!(f, "unexpected end of type"),
+            TypeError::Trailing { found } => write!(f, "unexpected `{}` after the type", found),
+            TypeError::NotNamed => write!(f, "only named types can take parameters"),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// The canonical name of a type, for the various spellings used in docs. Names that are not in
+/// here and start with a lowercase letter are type variables.
+fn canonical_name(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "string" | "String" | "str" => "string",
+        "int" | "Int" | "integer" | "Integer" => "int",
+        "float" | "Float" => "float",
+        "number" | "Number" => "number",
+        "bool" | "Bool" | "boolean" | "Boolean" => "bool",
+        "path" | "Path" => "path",
+        "null" | "Null" => "null",
+        "attrs" | "Attrs" | "attrset" | "AttrSet" | "Attrset" | "attrsOf" | "AttrsOf" | "set"
+        | "Set" => "attrs",
+        "list" | "List" | "listOf" | "ListOf" => "list",
+        "derivation" | "Derivation" | "drv" => "derivation",
+        "function" | "Function" | "lambda" => "function",
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Arrow,
+    DoubleColon,
+    Pipe,
+    Comma,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    /// A whole `{ ... }` attribute set type, whose contents we don't look at
+    Record,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Token::Ident(name) => name,
+            Token::Arrow => "->",
+            Token::DoubleColon => "::",
+            Token::Pipe => "|",
+            Token::Comma => ",",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Record => "{ ... }",
+        })
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, TypeError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let token = match ch {
+            c if c.is_whitespace() => continue,
+            '-' if chars.peek() == Some(&'>') => {
+                chars.next();
+                Token::Arrow
+            }
+            ':' if chars.peek() == Some(&':') => {
+                chars.next();
+                Token::DoubleColon
+            }
+            '|' => Token::Pipe,
+            ',' => Token::Comma,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '{' => {
+                let mut depth = 1;
+                for ch in chars.by_ref() {
+                    match ch {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                if depth != 0 {
+                    return Err(TypeError::UnclosedRecord);
+                }
+                Token::Record
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    // names like `the-fn`, but not the start of `a->b`
+                    let is_dash = c == '-' && {
+                        let mut ahead = chars.clone();
+                        ahead.next();
+                        ahead.peek() != Some(&'>')
+                    };
+                    if c.is_alphanumeric() || "_'.".contains(c) || is_dash {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(TypeError::UnexpectedChar(c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), TypeError> {
+        match self.next() {
+            Some(t) if t == expected => Ok(()),
+            Some(t) => Err(TypeError::Unexpected {
+                found: t.to_string(),
+                expected: Some(expected.to_string()),
+            }),
+            None => Err(TypeError::UnexpectedEnd {
+                expected: Some(expected.to_string()),
+            }),
+        }
+    }
+
+    fn function(&mut self) -> Result<Type, TypeError> {
+        let arg = self.union()?;
+        if self.peek() == Some(&Token::Arrow) {
+            self.next();
+            Ok(Type::Fun(Box::new(arg), Box::new(self.function()?)))
+        } else {
+            Ok(arg)
+        }
+    }
+
+    fn union(&mut self) -> Result<Type, TypeError> {
+        let mut alternatives = vec![self.application()?];
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            alternatives.push(self.application()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.remove(0)
+        } else {
+            Type::Union(alternatives)
+        })
+    }
+
+    fn starts_atom(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token::Ident(_) | Token::LParen | Token::LBracket | Token::Record)
+        )
+    }
+
+    fn application(&mut self) -> Result<Type, TypeError> {
+        let head = self.atom()?;
+        let mut args = Vec::new();
+        while self.starts_atom() {
+            args.push(self.atom()?);
+        }
+        match head {
+            _ if args.is_empty() => Ok(head),
+            Type::Con(name, params) if params.is_empty() => Ok(Type::Con(name, args)),
+            // higher kinded variables like `f a` don't show up in nix docs
+            _ => Err(TypeError::NotNamed),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Type, TypeError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(match canonical_name(&name) {
+                Some(name) => Type::Con(name.to_string(), Vec::new()),
+                None if name == "any" || name == "Any" => Type::Any,
+                None if name.starts_with(char::is_lowercase) => Type::Var(name),
+                None => Type::Con(name.to_lowercase(), Vec::new()),
+            }),
+            Some(Token::Record) => Ok(Type::Con("attrs".to_string(), Vec::new())),
+            Some(Token::LBracket) => {
+                let elem = self.function()?;
+                self.expect(Token::RBracket)?;
+                Ok(Type::Con("list".to_string(), vec![elem]))
+            }
+            Some(Token::LParen) => {
+                if self.peek() == Some(&Token::RParen) {
+                    self.next();
+                    return Ok(Type::Con("null".to_string(), Vec::new()));
+                }
+                let mut elems = vec![self.function()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.next();
+                    elems.push(self.function()?);
+                }
+                self.expect(Token::RParen)?;
+                Ok(if elems.len() == 1 {
+                    elems.remove(0)
+                } else {
+                    Type::Con("tuple".to_string(), elems)
+                })
+            }
+            Some(t) => Err(TypeError::Unexpected {
+                found: t.to_string(),
+                expected: None,
+            }),
+            None => Err(TypeError::UnexpectedEnd { expected: None }),
+        }
+    }
+}
+
+impl Type {
+    /// Parses a type, e.g. `string -> int -> string`
+    pub fn parse(s: &str) -> Result<Type, TypeError> {
+        Self::parse_tokens(tokenize(s)?)
+    }
+
+    /// Parses a declared type signature, which may start with the name of the function, e.g.
+    /// `fixedWidthString :: int -> string -> string`
+    pub fn parse_signature(s: &str) -> Result<Type, TypeError> {
+        let mut tokens = tokenize(s)?;
+        if let Some(pos) = tokens.iter().position(|t| *t == Token::DoubleColon) {
+            tokens.drain(..=pos);
+        }
+        Self::parse_tokens(tokens)
+    }
+
+    fn parse_tokens(tokens: Vec<Token>) -> Result<Type, TypeError> {
+        let mut parser = Parser { tokens, pos: 0 };
+        let ty = parser.function()?;
+        match parser.peek() {
+            None => Ok(ty),
+            Some(t) => Err(TypeError::Trailing {
+                found: t.to_string(),
+            }),
+        }
+    }
+
+    /// Renames the type variables to `a`, `b`, ... in order of appearance, so that types that
+    /// only differ in variable names are equal
+    pub fn normalize(&self) -> Type {
+        fn go(ty: &Type, names: &mut HashMap<String, String>) -> Type {
+            match ty {
+                Type::Var(v) => {
+                    let next = names.len();
+                    let name = names.entry(v.clone()).or_insert_with(|| var_name(next));
+                    Type::Var(name.clone())
+                }
+                _ => ty.map_children(|child| go(child, names)),
+            }
+        }
+        go(self, &mut HashMap::new())
+    }
+
+    fn map_children(&self, mut f: impl FnMut(&Type) -> Type) -> Type {
+        match self {
+            Type::Var(_) | Type::Any => self.clone(),
+            Type::Con(name, args) => Type::Con(name.clone(), args.iter().map(&mut f).collect()),
+            Type::Fun(arg, ret) => Type::Fun(Box::new(f(arg)), Box::new(f(ret))),
+            Type::Union(alts) => Type::Union(alts.iter().map(f).collect()),
+        }
+    }
+
+    /// Puts `prefix` in front of all variable names, to keep the variables of two types apart
+    fn tag_vars(&self, prefix: &str) -> Type {
+        match self {
+            Type::Var(v) => Type::Var(format!("{}{}", prefix, v)),
+            _ => self.map_children(|child| child.tag_vars(prefix)),
+        }
+    }
+
+    /// Splits a function type into its arguments and final result
+    fn uncurry(&self) -> (Vec<&Type>, &Type) {
+        let mut args = Vec::new();
+        let mut ty = self;
+        while let Type::Fun(arg, ret) = ty {
+            args.push(&**arg);
+            ty = ret;
+        }
+        (args, ty)
+    }
+
+    fn curry(args: &[&Type], ret: &Type) -> Type {
+        args.iter().rev().fold(ret.clone(), |ret, arg| {
+            Type::Fun(Box::new((*arg).clone()), Box::new(ret))
+        })
+    }
+
+    /// How well `candidate`, a declared type, matches this query
+    pub fn matches(&self, candidate: &Type) -> Option<TypeMatch> {
+        if self.normalize() == candidate.normalize() {
+            return Some(TypeMatch::Exact);
+        }
+
+        let query = self.tag_vars("q.");
+        let candidate = candidate.tag_vars("c.");
+        if unify(&query, &candidate, &mut HashMap::new()) {
+            return Some(TypeMatch::Unifies);
+        }
+
+        let (query_args, query_ret) = query.uncurry();
+        let (candidate_args, candidate_ret) = candidate.uncurry();
+        // trying every order gets expensive quickly, and functions that take this many arguments
+        // are rare anyway
+        if query_args.len() != candidate_args.len() || query_args.len() > 6 {
+            return None;
+        }
+        let candidate = Type::curry(&candidate_args, candidate_ret);
+        permutations(query_args.len())
+            .into_iter()
+            .map(|order| order.iter().map(|&i| query_args[i]).collect::<Vec<_>>())
+            .any(|args| {
+                unify(
+                    &Type::curry(&args, query_ret),
+                    &candidate,
+                    &mut HashMap::new(),
+                )
+            })
+            .then_some(TypeMatch::Reordered)
+    }
+}
+
+fn var_name(n: usize) -> String {
+    let letter = (b'a' + (n % 26) as u8) as char;
+    match n / 26 {
+        0 => letter.to_string(),
+        k => format!("{}{}", letter, k),
+    }
+}
+
+/// All orders of `0..n`
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![Vec::new()];
+    }
+    let mut out = Vec::new();
+    for perm in permutations(n - 1) {
+        for pos in 0..=perm.len() {
+            let mut perm = perm.clone();
+            perm.insert(pos, n - 1);
+            out.push(perm);
+        }
+    }
+    out
+}
+
+/// Follows the substitution for variables that are already bound
+fn resolve(ty: &Type, subst: &HashMap<String, Type>) -> Type {
+    match ty {
+        Type::Var(v) => match subst.get(v) {
+            Some(bound) => resolve(bound, subst),
+            None => ty.clone(),
+        },
+        _ => ty.clone(),
+    }
+}
+
+fn occurs(var: &str, ty: &Type, subst: &HashMap<String, Type>) -> bool {
+    match resolve(ty, subst) {
+        Type::Var(v) => v == var,
+        Type::Any => false,
+        Type::Con(_, args) | Type::Union(args) => args.iter().any(|a| occurs(var, a, subst)),
+        Type::Fun(arg, ret) => occurs(var, &arg, subst) || occurs(var, &ret, subst),
+    }
+}
+
+/// Tries to make `a` and `b` the same type by binding variables in `subst`
+fn unify(a: &Type, b: &Type, subst: &mut HashMap<String, Type>) -> bool {
+    let a = resolve(a, subst);
+    let b = resolve(b, subst);
+    match (&a, &b) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Var(x), Type::Var(y)) if x == y => true,
+        (Type::Var(x), t) | (t, Type::Var(x)) => {
+            if occurs(x, t, subst) {
+                return false;
+            }
+            subst.insert(x.clone(), t.clone());
+            true
+        }
+        (Type::Union(alts), other) | (other, Type::Union(alts)) => alts.iter().any(|alt| {
+            let mut attempt = subst.clone();
+            let ok = unify(alt, other, &mut attempt);
+            if ok {
+                *subst = attempt;
+            }
+            ok
+        }),
+        (Type::Con(name, args), Type::Fun(..)) | (Type::Fun(..), Type::Con(name, args)) => {
+            name == "function" && args.is_empty()
+        }
+        (Type::Fun(a1, r1), Type::Fun(a2, r2)) => unify(a1, a2, subst) && unify(r1, r2, subst),
+        (Type::Con(n1, args1), Type::Con(n2, args2)) => {
+            // `list` written without its element type is compatible with any list
+            n1 == n2
+                && (args1.is_empty()
+                    || args2.is_empty()
+                    || args1.len() == args2.len()
+                        && args1.iter().zip(args2).all(|(x, y)| unify(x, y, subst)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn con(name: &str) -> Type {
+        Type::Con(name.to_string(), Vec::new())
+    }
+
+    fn fun(arg: Type, ret: Type) -> Type {
+        Type::Fun(Box::new(arg), Box::new(ret))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Type::parse_signature("fixedWidthString :: int -> String -> string").unwrap(),
+            fun(con("int"), fun(con("string"), con("string")))
+        );
+        assert_eq!(
+            Type::parse("(a -> b) -> [a] -> { x :: int; } | Null").unwrap(),
+            fun(
+                fun(Type::Var("a".to_string()), Type::Var("b".to_string())),
+                fun(
+                    Type::Con("list".to_string(), vec![Type::Var("a".to_string())]),
+                    Type::Union(vec![con("attrs"), con("null")])
+                )
+            )
+        );
+        assert_eq!(
+            Type::parse("AttrsOf Derivation").unwrap(),
+            Type::Con("attrs".to_string(), vec![con("derivation")])
+        );
+        assert_eq!(
+            Type::parse("a ->"),
+            Err(TypeError::UnexpectedEnd { expected: None })
+        );
+        assert_eq!(
+            Type::parse("(a").unwrap_err().to_string(),
+            "expected `)`, found the end of the type"
+        );
+        assert_eq!(
+            Type::parse("[a) b").unwrap_err().to_string(),
+            "expected `]` in type, found `)`"
+        );
+        assert_eq!(Type::parse("a % b"), Err(TypeError::UnexpectedChar('%')));
+    }
+
+    #[test]
+    fn matching() {
+        let check = |query: &str, candidate: &str| {
+            Type::parse(query)
+                .unwrap()
+                .matches(&Type::parse_signature(candidate).unwrap())
+        };
+        assert_eq!(
+            check("(x -> y) -> [x] -> [y]", "map :: (a -> b) -> [a] -> [b]"),
+            Some(TypeMatch::Exact)
+        );
+        assert_eq!(
+            check(
+                "(string -> int) -> [string] -> [int]",
+                "map :: (a -> b) -> [a] -> [b]"
+            ),
+            Some(TypeMatch::Unifies)
+        );
+        assert_eq!(
+            check("string -> int -> string", "int -> string -> string"),
+            Some(TypeMatch::Reordered)
+        );
+        assert_eq!(
+            check("string -> any", "string -> int"),
+            Some(TypeMatch::Unifies)
+        );
+        assert_eq!(check("a -> a", "string -> int"), None);
+        assert_eq!(check("string -> int", "string -> int -> int"), None);
+        assert_eq!(
+            check("function -> list", "(a -> b) -> [a]"),
+            Some(TypeMatch::Unifies)
+        );
+        assert_eq!(
+            check("string -> bool", "string | path -> bool"),
+            Some(TypeMatch::Unifies)
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: 2024 Jade Lovelace
+//
+// SPDX-License-Identifier: BSD-2-Clause OR MIT
+
+//! Finding the nix files to look at, shared between search and tags
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{DirEntry, WalkBuilder};
+
+use crate::{is_ignored, is_searchable};
+
+/// Which files under a directory are considered
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// Files larger than this many bytes are skipped. Very large files are generally generated
+    /// and slow to parse.
+    pub max_file_size: Option<u64>,
+
+    /// If set, only files matching one of these globs are considered
+    pub include: Option<GlobSet>,
+
+    /// Files and directories matching any of these globs are skipped
+    pub exclude: Option<GlobSet>,
+
+    /// Don't respect `.gitignore`, `.ignore` and global git excludes
+    pub no_ignore: bool,
+}
+
+/// Builds a set out of `globs`, or `None` if there are none.
+pub fn glob_set<I, S>(globs: I) -> Result<Option<GlobSet>, globset::Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut builder = GlobSetBuilder::new();
+    let mut any = false;
+    for glob in globs {
+        builder.add(Glob::new(glob.as_ref())?);
+        any = true;
+    }
+    if !any {
+        return Ok(None);
+    }
+    builder.build().map(Some)
+}
+
+/// Does `set` match the path of `entry` relative to `dir`, or its file name?
+///
+/// Matching the file name too means globs without slashes like `hackage-packages.nix` apply at any
+/// depth, like in a `.gitignore`.
+fn matches(set: &GlobSet, dir: &Path, entry: &DirEntry) -> bool {
+    let relative = entry
+        .path()
+        .strip_prefix(dir)
+        .unwrap_or_else(|_| entry.path());
+    set.is_match(relative) || set.is_match(entry.file_name())
+}
+
+impl WalkOptions {
+    fn is_included(&self, dir: &Path, entry: &DirEntry) -> bool {
+        match &self.include {
+            Some(set) => matches(set, dir, entry),
+            None => true,
+        }
+    }
+
+    fn is_small_enough(&self, entry: &DirEntry) -> bool {
+        match self.max_file_size {
+            Some(max) => fs::metadata(entry.path())
+                .map(|m| m.len() <= max)
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Finds all the nix files to consider under `dir`
+    pub fn files<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+        // the filter has to be 'static, so it gets its own copies
+        let exclude = self.exclude.clone();
+        let root = dir.to_owned();
+
+        WalkBuilder::new(dir)
+            // .gitignore, .ignore, global git excludes and so on, the same way ripgrep does it
+            .standard_filters(!self.no_ignore)
+            // hidden files are handled by is_ignored, which lets us pass "." as a directory
+            .hidden(false)
+            .filter_entry(move |e| {
+                let excluded = match &exclude {
+                    Some(set) => matches(set, &root, e),
+                    None => false,
+                };
+                !is_ignored(e) && !excluded
+            })
+            .build()
+            .filter_map(|e| e.ok())
+            .filter(move |e| {
+                is_searchable(e.path())
+                    && e.path().is_file()
+                    && self.is_included(dir, e)
+                    && self.is_small_enough(e)
+            })
+            .map(DirEntry::into_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(options: WalkOptions, expected: &[&str]) {
+        let mut files = options
+            .files(Path::new("testdata"))
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn filters() {
+        check(
+            WalkOptions::default(),
+            &[
+                "testdata/regression-11.nix",
+                "testdata/test.nix",
+                "testdata/test2.nix",
+            ],
+        );
+        check(
+            WalkOptions {
+                include: glob_set(["test*"]).unwrap(),
+                exclude: glob_set(["test2.nix"]).unwrap(),
+                ..WalkOptions::default()
+            },
+            &["testdata/test.nix"],
+        );
+        check(
+            WalkOptions {
+                max_file_size: Some(100),
+                ..WalkOptions::default()
+            },
+            &["testdata/test2.nix"],
+        );
+        check(
+            WalkOptions {
+                no_ignore: true,
+                ..WalkOptions::default()
+            },
+            &[
+                "testdata/ignore/generated.nix",
+                "testdata/regression-11.nix",
+                "testdata/test.nix",
+                "testdata/test2.nix",
+            ],
+        );
+    }
+}
@@ -0,0 +1,4 @@
+{
+  # generated, should be ignored
+  generated = x: x;
+}
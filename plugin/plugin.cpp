@@ -24,7 +24,8 @@
 using namespace nix;
 
 extern "C" {
-char const *nd_get_function_docs(char const *filename, size_t line, size_t col);
+char const *nd_get_function_docs(char const *filename, size_t line, size_t col,
+                                 bool allow_color);
 void nd_free_string(char const *str);
 }
 
@@ -39,9 +40,10 @@ struct NdString {
   }
 };
 
-NdString docs_for_pos(nix::Pos const &pos) {
+NdString docs_for_pos(nix::Pos const &pos, bool allow_color) {
   std::string const file = compat::fileForPos(pos);
-  return NdString{nd_get_function_docs(file.c_str(), pos.line, pos.column)};
+  return NdString{
+      nd_get_function_docs(file.c_str(), pos.line, pos.column, allow_color)};
 }
 
 /* Print documentation of the given lambda. */
@@ -52,7 +54,8 @@ void prim_getDoc(EvalState &state, compat::ConstPos pos, Value **args,
   compat::forceLambda(state, *args[0], pos);
 
   auto poz = compat::posForLambda(state, *args[0]->lambda.fun);
-  auto doc = docs_for_pos(poz);
+  // strings are for further processing, so keep escape codes out of them
+  auto doc = docs_for_pos(poz, false);
   if (doc.is_null()) {
     compat::mkNull(v);
   } else {
@@ -64,7 +67,7 @@ void prim_getDoc(EvalState &state, compat::ConstPos pos, Value **args,
 void printLambdaDocs(EvalState &state, Value &v) {
   auto poz = compat::posForLambda(state, *v.lambda.fun);
 
-  auto docs = docs_for_pos(poz);
+  auto docs = docs_for_pos(poz, true);
   if (!docs.is_null()) {
     std::cout << docs.docs << std::endl;
   }
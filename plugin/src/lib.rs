@@ -7,32 +7,47 @@ use std::os::raw::c_char;
 use std::panic;
 use std::ptr;
 
-use nix_doc::get_function_docs;
+use nix_doc::{get_function_docs, ColorChoice, FormatOptions};
 
 /// Get the docs for a function in the given file path at the given file position and return it as
-/// a C string pointer
+/// a C string pointer, or null after printing what went wrong to stderr.
+///
+/// If `allow_color` is set, the docs are coloured when stdout is a terminal and `NO_COLOR` is not
+/// set; otherwise they are plain text.
 #[no_mangle]
 pub extern "C" fn nd_get_function_docs(
     filename: *const c_char,
     line: usize,
     col: usize,
+    allow_color: bool,
 ) -> *const c_char {
     let fname = unsafe { CStr::from_ptr(filename) };
-    fname
-        .to_str()
-        .ok()
-        .and_then(|f| {
-            panic::catch_unwind(|| get_function_docs(f, line, col))
-                .map_err(|e| {
-                    eprintln!("panic!! {:#?}", e);
-                    e
-                })
-                .ok()
-        })
-        .flatten()
-        .and_then(|s| CString::new(s).ok())
-        .map(|s| s.into_raw() as *const c_char)
-        .unwrap_or(ptr::null())
+    let options = if allow_color {
+        FormatOptions::with_color(ColorChoice::Auto)
+    } else {
+        FormatOptions::plain()
+    };
+    let fname = match fname.to_str() {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("nix-doc: file name is not valid UTF-8: {}", e);
+            return ptr::null();
+        }
+    };
+    let docs = panic::catch_unwind(|| get_function_docs(fname, line, col, &options));
+    match docs {
+        Ok(Ok(docs)) => CString::new(docs)
+            .map(|s| s.into_raw() as *const c_char)
+            .unwrap_or(ptr::null()),
+        Ok(Err(e)) => {
+            eprintln!("nix-doc: {}:{}:{}: {}", fname, line, col, e);
+            ptr::null()
+        }
+        Err(e) => {
+            eprintln!("panic!! {:#?}", e);
+            ptr::null()
+        }
+    }
 }
 
 /// Call this to free a string from nd_get_function_docs
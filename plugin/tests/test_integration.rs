@@ -98,7 +98,7 @@ fn test_builtins_doc() {
                line one
                line two
                line three
-            func = x: ...
+            addOne = x: ...
             # test.nix:5
         "#]],
     );
@@ -109,7 +109,10 @@ fn test_builtins_doc() {
                this one
                has multiple
                comments
-            func = { b, /* doc */ c }: ...
+
+               Arguments:
+                 c  doc
+            the-snd-fn = { b, c }: ...
             # test.nix:10
         "#]],
     );
@@ -123,7 +126,10 @@ fn test_builtins_getdoc() {
                this one
                has multiple
                comments
-            func = { b, /* doc */ c }: ...
+
+               Arguments:
+                 c  doc
+            the-snd-fn = { b, c }: ...
             # test.nix:10"#]],
     );
 }